@@ -0,0 +1,84 @@
+// How long Controller::update takes per report, with a sink that does
+// nothing so only the mapping is measured. `cargo bench` to run it.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use tfc::{Key, MouseButton};
+
+use mac_usb_controller::config::Config;
+use mac_usb_controller::sink::OutputSink;
+use mac_usb_controller::{Controller, Input};
+
+struct NullSink;
+
+impl OutputSink for NullSink {
+    fn key_down(&mut self, _key: Key) -> Result<(), tfc::Error> {
+        Ok(())
+    }
+
+    fn key_up(&mut self, _key: Key) -> Result<(), tfc::Error> {
+        Ok(())
+    }
+
+    fn mouse_down(&mut self, _button: MouseButton) -> Result<(), tfc::Error> {
+        Ok(())
+    }
+
+    fn mouse_up(&mut self, _button: MouseButton) -> Result<(), tfc::Error> {
+        Ok(())
+    }
+
+    fn mouse_move_rel(&mut self, _dx: i32, _dy: i32) -> Result<(), tfc::Error> {
+        Ok(())
+    }
+
+    fn mouse_scroll(&mut self, _dx: i32, _dy: i32) -> Result<(), tfc::Error> {
+        Ok(())
+    }
+
+    fn type_text(&mut self, _text: &str) -> Result<(), tfc::Error> {
+        Ok(())
+    }
+}
+
+// A second of play at the pad's ~1ms report rate: face buttons mashed,
+// the dpad rolled round, the left stick swept in a circle and the right
+// one nudged now and then. Most reports change something, as in a match.
+fn reports() -> Vec<Input> {
+    (0..1000u32)
+        .map(|i| {
+            let angle = i as f32 / 100.0;
+            let buttons = [0x00, 0x04, 0x0c, 0x08, 0x00, 0x02][(i / 7 % 6) as usize];
+            let dpad = [15, 6, 5, 4, 3, 2, 15][(i / 11 % 7) as usize];
+            let rstick = if i / 50 % 4 == 0 { 200 } else { 128 };
+
+            Input::new([
+                buttons,
+                0,
+                dpad,
+                (128.0 + angle.cos() * 127.0) as u8,
+                (128.0 + angle.sin() * 127.0) as u8,
+                128,
+                rstick,
+                0,
+            ])
+        })
+        .collect()
+}
+
+fn update(c: &mut Criterion) {
+    let reports = reports();
+    let mut controller = Controller::new(&Config::default(), 0);
+    let mut sink = NullSink;
+
+    let mut group = c.benchmark_group("update");
+    group.throughput(Throughput::Elements(reports.len() as u64));
+    group.bench_function("default config", |b| b.iter(|| {
+        for &input in &reports {
+            controller.update(black_box(input), &mut sink).unwrap();
+        }
+    }));
+    group.finish();
+}
+
+criterion_group!(benches, update);
+criterion_main!(benches);
@@ -0,0 +1,147 @@
+// Which application is focused ("com.apple.Safari" and so on), for
+// switching profiles to match
+
+#[cfg(all(target_os = "macos", feature = "app-profiles"))]
+pub use watcher::AppWatcher;
+
+// Without the feature, or off macOS, a watcher can never be started
+#[cfg(not(all(target_os = "macos", feature = "app-profiles")))]
+pub enum AppWatcher {}
+
+#[cfg(not(all(target_os = "macos", feature = "app-profiles")))]
+impl AppWatcher {
+    pub fn start() -> std::io::Result<AppWatcher> {
+        Err(std::io::Error::other("built without the app-profiles feature, or not on macOS"))
+    }
+
+    pub fn changed(&self) -> Option<Option<String>> {
+        match *self {}
+    }
+}
+
+#[cfg(all(target_os = "macos", feature = "app-profiles"))]
+mod watcher {
+    use std::ffi::{c_void, CStr};
+    use std::io;
+    use std::os::raw::c_char;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    use objc::runtime::Object;
+    use objc::{class, msg_send, sel, sel_impl};
+
+    // Switching apps is a human speed thing, no need to check often
+    const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+    // CGWindowListOption bits and the CFNumberType for an i32
+    const ON_SCREEN_ONLY: u32 = 1 << 0;
+    const EXCLUDE_DESKTOP_ELEMENTS: u32 = 1 << 4;
+    const NUMBER_SINT32: isize = 3;
+
+    type CFTypeRef = *const c_void;
+
+    #[link(name = "CoreGraphics", kind = "framework")]
+    extern "C" {
+        static kCGWindowLayer: CFTypeRef;
+        static kCGWindowOwnerPID: CFTypeRef;
+        fn CGWindowListCopyWindowInfo(option: u32, relative_to: u32) -> CFTypeRef;
+    }
+
+    #[link(name = "CoreFoundation", kind = "framework")]
+    extern "C" {
+        fn CFArrayGetCount(array: CFTypeRef) -> isize;
+        fn CFArrayGetValueAtIndex(array: CFTypeRef, index: isize) -> CFTypeRef;
+        fn CFDictionaryGetValue(dict: CFTypeRef, key: CFTypeRef) -> CFTypeRef;
+        fn CFNumberGetValue(number: CFTypeRef, kind: isize, value: *mut c_void) -> u8;
+        fn CFRelease(cf: CFTypeRef);
+    }
+
+    // for NSRunningApplication
+    #[link(name = "AppKit", kind = "framework")]
+    extern "C" {}
+
+    // Polls on its own thread and passes on each change of focused app,
+    // None when it has no bundle id
+    pub struct AppWatcher {
+        rx: mpsc::Receiver<Option<String>>,
+    }
+
+    impl AppWatcher {
+        pub fn start() -> io::Result<AppWatcher> {
+            let (tx, rx) = mpsc::channel();
+
+            thread::Builder::new().name("app-watcher".to_string()).spawn(move || {
+                let mut last = None;
+
+                loop {
+                    let app = frontmost_app();
+                    if app != last {
+                        // dispatch has finished with us
+                        if tx.send(app.clone()).is_err() { return; }
+                        last = app;
+                    }
+
+                    thread::sleep(POLL_INTERVAL);
+                }
+            })?;
+
+            Ok(AppWatcher { rx })
+        }
+
+        // The latest app since the last call, if it changed
+        pub fn changed(&self) -> Option<Option<String>> {
+            self.rx.try_iter().last()
+        }
+    }
+
+    fn frontmost_app() -> Option<String> {
+        let pid = frontmost_pid()?;
+
+        objc::rc::autoreleasepool(|| unsafe {
+            let app: *mut Object = msg_send![class!(NSRunningApplication), runningApplicationWithProcessIdentifier: pid];
+            if app.is_null() { return None; }
+
+            let bundle: *mut Object = msg_send![app, bundleIdentifier];
+            if bundle.is_null() { return None; }
+
+            let utf8: *const c_char = msg_send![bundle, UTF8String];
+            if utf8.is_null() { return None; }
+
+            Some(CStr::from_ptr(utf8).to_string_lossy().into_owned())
+        })
+    }
+
+    // Windows come back front to back, and the first on the normal window
+    // layer belongs to the focused app. NSWorkspace's frontmostApplication
+    // would be simpler but it only updates while the main thread runs a
+    // run loop, which ours never does.
+    fn frontmost_pid() -> Option<i32> {
+        unsafe {
+            let windows = CGWindowListCopyWindowInfo(ON_SCREEN_ONLY | EXCLUDE_DESKTOP_ELEMENTS, 0);
+            if windows.is_null() { return None; }
+
+            let mut pid = None;
+            for i in 0..CFArrayGetCount(windows) {
+                let window = CFArrayGetValueAtIndex(windows, i);
+                if number(window, kCGWindowLayer) == Some(0) {
+                    pid = number(window, kCGWindowOwnerPID);
+                    break;
+                }
+            }
+
+            CFRelease(windows);
+            pid
+        }
+    }
+
+    unsafe fn number(dict: CFTypeRef, key: CFTypeRef) -> Option<i32> {
+        let value = CFDictionaryGetValue(dict, key);
+        if value.is_null() { return None; }
+
+        let mut n: i32 = 0;
+        if CFNumberGetValue(value, NUMBER_SINT32, &mut n as *mut i32 as *mut c_void) == 0 { return None; }
+
+        Some(n)
+    }
+}
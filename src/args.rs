@@ -0,0 +1,334 @@
+use std::convert::TryFrom;
+use std::env;
+use std::path::PathBuf;
+use std::process;
+use std::str::FromStr;
+use std::time::Duration;
+
+use mac_usb_controller::layout::Layout;
+use mac_usb_controller::sink::Backend;
+use mac_usb_controller::{DeviceMatch, PollOptions};
+
+const DEFAULT_DEVICE: &str = "HORIPAD S";
+
+const USAGE: &str = "usage: mac-usb-controller [--device NAME | --vid ID --pid ID | --serial SERIAL] [--config PATH [--watch]]
+                          [--dry-run | --backend NAME] [--record PATH | --replay PATH] [--poll-interval-ms MS]
+                          [--clear-every N] [--read-timeout-ms MS] [--max-read-errors N] [--report-id ID]
+                          [--layout NAME] [--usage-page ID] [--usage ID] [--interface N]
+                          [--reconnect-min-ms MS] [--reconnect-max-ms MS] [--overlay ADDR]
+                          [--watchdog-ms MS] [--latency SECS] [--drops SECS]
+                          [--max-reports N] [--log-file PATH] [--control PATH] [-v...]
+       mac-usb-controller list-devices
+       mac-usb-controller --calibrate [--config PATH]
+       mac-usb-controller --rebind [--config PATH]
+       mac-usb-controller --test
+       mac-usb-controller --check-config PATH
+       mac-usb-controller --install [options] | --uninstall
+
+commands:
+    list-devices     print every HID device that can be seen, then exit
+
+options:
+    --device NAME    product string of the controller to open (default \"HORIPAD S\")
+    --vid ID         USB vendor id to match instead, hex (0x0f0d) or decimal
+    --pid ID         USB product id to match instead, hex (0x00c1) or decimal
+    --serial SERIAL  serial number of the one controller to open, for telling
+                     identical pads apart
+    --config PATH    TOML file of button to key mappings
+    --watch          reload the config whenever it's saved
+    --calibrate      measure the resting stick positions and save them to the
+                     config, or print them if there isn't one
+    --rebind         bind buttons to keys by pressing each one and typing its
+                     key, then save them as the config's [buttons], or print
+                     them if there isn't one
+    --test           show what the controller reports, live, for 30 seconds
+                     without sending anything
+    --check-config PATH
+                     load and check a config without a controller, print OK
+                     and any warnings or the first problem, then exit
+    --install        start at login with the other options given, through a
+                     launchd agent in ~/Library/LaunchAgents, and print the
+                     launchctl command that starts it now
+    --uninstall      remove that agent
+    --dry-run        log the key and mouse events instead of sending them
+    --backend NAME   where events go: tfc (the default, real key presses),
+                     stdout (a line of JSON per event), uinput (a virtual
+                     device, Linux only) or pad (the controller passed on
+                     as a virtual gamepad, Linux with the virtual-pad
+                     feature)
+    --record PATH    write every raw report read, with its time, to PATH
+    --replay PATH    play back a recording at its original pace instead of
+                     opening a device
+    --poll-interval-ms MS
+                     sleep between device reads (default 1). 0 gives the
+                     lowest latency but keeps a core busy, higher values
+                     save CPU at the cost of input lag
+    --clear-every N  resync held buttons every N polls to recover from
+                     dropped reports (default 7), 0 disables it
+    --read-timeout-ms MS
+                     longest to wait for a report before holding the last
+                     input (default 100)
+    --max-read-errors N
+                     failed reads in a row before the controller is treated
+                     as unplugged, its keys let go and it's reopened
+                     (default 100). Fewer are taken as nothing held
+    --reconnect-min-ms MS
+                     wait before the first try at reopening an unplugged
+                     controller (default 100), doubled after each failed try
+    --reconnect-max-ms MS
+                     longest wait between tries (default 5000)
+    --watchdog-ms MS reopen a controller that hasn't sent a report in MS,
+                     for pads that go quiet in sleep mode without an error.
+                     Only for pads that report even when nothing changes.
+                     0, the default, never does
+    --report-id ID   for pads whose reports start with a report id byte,
+                     hex (0x01) or decimal. Without it one is still noticed
+                     and skipped, this only makes sure it's the right one
+    --layout NAME    how the pad's reports are laid out: horipad (the
+                     default) or generic (sticks, hat, then 13 buttons)
+    --usage-page ID  only open the matching controller's HID interface with
+                     this usage page, hex or decimal, for pads with several
+                     interfaces where the one picked sends nothing. Without
+                     any of these the gamepad interface is preferred
+    --usage ID       only open its interface with this usage
+    --interface N    only open its interface with this number
+    --max-reports N  let go of everything and exit 0 after N reports, or at
+                     the end of a --replay if that's sooner. 0 never stops
+    --latency SECS   log how long reports take to turn into events (min, avg,
+                     max and p99) every SECS seconds
+    --drops SECS     log how many reports were lost on the way from the pad
+                     every SECS seconds, for pads that count their reports
+                     in the last byte
+    --overlay ADDR   serve the live input as JSON over a WebSocket on ADDR,
+                     e.g. 127.0.0.1:9001 (needs the overlay feature)
+    --log-file PATH  log to PATH instead of stderr, moving it to PATH.1 once
+                     it reaches 10MB and keeping two of those
+    --control PATH   take commands from scripts on a Unix socket at PATH:
+                     profile NAME, reload, state and release-all
+    -v, --verbose    log more, repeat for more still (-v debug, -vv trace).
+                     RUST_LOG overrides this when it's set
+    -h, --help       print this message
+
+exit codes:
+    0  clean shutdown        4  device not found
+    1  file or socket error  5  device could not be opened or read
+    2  bad arguments         6  key events could not be sent
+    3  bad config            7  calibration failed
+                             8  key events could not be started";
+
+#[derive(Debug)]
+pub struct Args {
+    pub target: DeviceMatch,
+    pub config: Option<PathBuf>,
+    pub check_config: Option<PathBuf>,
+    pub watch: bool,
+    pub list_devices: bool,
+    pub dry_run: bool,
+    pub backend: Backend,
+    pub calibrate: bool,
+    pub rebind: bool,
+    pub test: bool,
+    pub record: Option<PathBuf>,
+    pub replay: Option<PathBuf>,
+    pub overlay: Option<String>,
+    // Unix socket for --control commands
+    pub control: Option<PathBuf>,
+    // every argument but --install, for the launchd agent it writes
+    pub install: Option<Vec<String>>,
+    pub uninstall: bool,
+    pub poll: PollOptions,
+    pub log_file: Option<PathBuf>,
+    // how many times -v was given
+    pub verbose: u8,
+}
+
+impl Args {
+    pub fn parse() -> Result<Args, String> {
+        Args::parse_from(env::args().skip(1))
+    }
+
+    fn parse_from<I: Iterator<Item = String>>(args: I) -> Result<Args, String> {
+        let given: Vec<String> = args.collect();
+        let mut args = given.iter().cloned();
+        let mut device = DEFAULT_DEVICE.to_string();
+        let mut config = None;
+        let mut check_config = None;
+        let mut watch = false;
+        let mut vid = None;
+        let mut pid = None;
+        let mut serial = None;
+        let mut list_devices = false;
+        let mut dry_run = false;
+        let mut backend = None;
+        let mut calibrate = false;
+        let mut rebind = false;
+        let mut test = false;
+        let mut record = None;
+        let mut replay = None;
+        let mut overlay = None;
+        let mut control = None;
+        let mut log_file = None;
+        let mut install = false;
+        let mut uninstall = false;
+        let mut poll = PollOptions::default();
+        let mut verbose: u8 = 0;
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--device" => device = args.next().ok_or("--device requires a value")?,
+                "--vid" => vid = Some(parse_id("--vid", args.next())?),
+                "--pid" => pid = Some(parse_id("--pid", args.next())?),
+                "--serial" => serial = Some(args.next().ok_or("--serial requires a value")?),
+                "--config" => config = Some(PathBuf::from(args.next().ok_or("--config requires a value")?)),
+                "--check-config" => check_config = Some(PathBuf::from(args.next().ok_or("--check-config requires a value")?)),
+                "--poll-interval-ms" => poll.interval = Duration::from_millis(parse_number("--poll-interval-ms", args.next())?),
+                "--clear-every" => poll.clear_every = parse_number("--clear-every", args.next())?,
+                "--read-timeout-ms" => poll.read_timeout = Duration::from_millis(parse_number("--read-timeout-ms", args.next())?),
+                "--max-read-errors" => {
+                    poll.max_read_errors = parse_number("--max-read-errors", args.next())?;
+                    if poll.max_read_errors == 0 { return Err("--max-read-errors must be at least 1".to_string()); }
+                },
+                "--reconnect-min-ms" => poll.reconnect_min = Duration::from_millis(parse_number("--reconnect-min-ms", args.next())?),
+                "--reconnect-max-ms" => poll.reconnect_max = Duration::from_millis(parse_number("--reconnect-max-ms", args.next())?),
+                "--watchdog-ms" => poll.watchdog = match parse_number("--watchdog-ms", args.next())? {
+                    0 => None,
+                    ms => Some(Duration::from_millis(ms)),
+                },
+                "--report-id" => {
+                    let id = parse_id("--report-id", args.next())?;
+                    poll.report_id = Some(u8::try_from(id).map_err(|_| format!("Invalid --report-id {:#x}", id))?);
+                },
+                "--layout" => poll.layout = parse_layout(args.next())?,
+                "--usage-page" => poll.interface.usage_page = Some(parse_id("--usage-page", args.next())?),
+                "--usage" => poll.interface.usage = Some(parse_id("--usage", args.next())?),
+                "--interface" => poll.interface.number = Some(parse_number("--interface", args.next())?),
+                "--max-reports" => poll.max_reports = match parse_number("--max-reports", args.next())? {
+                    0 => None,
+                    n => Some(n),
+                },
+                "--watch" => watch = true,
+                "--latency" => {
+                    let secs: u64 = parse_number("--latency", args.next())?;
+                    if secs == 0 { return Err("--latency must be at least 1 second".to_string()); }
+                    poll.latency = Some(Duration::from_secs(secs));
+                },
+                "--drops" => {
+                    let secs: u64 = parse_number("--drops", args.next())?;
+                    if secs == 0 { return Err("--drops must be at least 1 second".to_string()); }
+                    poll.drops = Some(Duration::from_secs(secs));
+                },
+                "--dry-run" => dry_run = true,
+                "--backend" => backend = Some(parse_backend(args.next())?),
+                "--calibrate" => calibrate = true,
+                "--rebind" => rebind = true,
+                "--test" => test = true,
+                "--record" => record = Some(PathBuf::from(args.next().ok_or("--record requires a value")?)),
+                "--replay" => replay = Some(PathBuf::from(args.next().ok_or("--replay requires a value")?)),
+                "--overlay" => overlay = Some(args.next().ok_or("--overlay requires a value")?),
+                "--control" => control = Some(PathBuf::from(args.next().ok_or("--control requires a value")?)),
+                "--log-file" => log_file = Some(PathBuf::from(args.next().ok_or("--log-file requires a value")?)),
+                "--install" => install = true,
+                "--uninstall" => uninstall = true,
+                "list-devices" => list_devices = true,
+                "-v" | "--verbose" => verbose = verbose.saturating_add(1),
+                // -vv and so on
+                flag if flag.len() > 2 && flag.starts_with('-') && flag[1..].bytes().all(|b| b == b'v') => {
+                    verbose = verbose.saturating_add((flag.len() - 1) as u8);
+                },
+                "-h" | "--help" => {
+                    println!("{}", USAGE);
+                    process::exit(0);
+                },
+                _ => return Err(format!("Unknown argument {:?}\n{}", arg, USAGE)),
+            }
+        }
+
+        if device.trim().is_empty() {
+            return Err("--device must not be empty".to_string());
+        }
+
+        if watch && config.is_none() {
+            return Err("--watch needs a --config to watch".to_string());
+        }
+
+        if dry_run && backend.is_some() {
+            return Err("--dry-run and --backend can't be used together".to_string());
+        }
+
+        if poll.reconnect_min.is_zero() {
+            return Err("--reconnect-min-ms must be at least 1".to_string());
+        }
+
+        if poll.reconnect_max < poll.reconnect_min {
+            return Err("--reconnect-max-ms can't be less than --reconnect-min-ms".to_string());
+        }
+
+        if record.is_some() && replay.is_some() {
+            return Err("--record and --replay can't be used together".to_string());
+        }
+
+        if serial.as_deref().is_some_and(|serial: &str| serial.trim().is_empty()) {
+            return Err("--serial must not be empty".to_string());
+        }
+
+        if install && (uninstall || list_devices || calibrate || rebind || test || check_config.is_some()) {
+            return Err("--install only takes the options to run with".to_string());
+        }
+
+        // VID/PID is more specific than the product string so it wins when both
+        // are given, and a serial picks out one unit so it beats both
+        let target = match (serial, vid, pid) {
+            (Some(serial), _, _) => DeviceMatch::BySerial(serial),
+            (None, Some(vid), Some(pid)) => DeviceMatch::ByVidPid(vid, pid),
+            (None, None, None) => DeviceMatch::ByName(device),
+            _ => return Err("--vid and --pid must be given together".to_string()),
+        };
+
+        let backend = backend.unwrap_or(Backend::Tfc);
+        let install = if install { Some(given.into_iter().filter(|arg| arg != "--install").collect()) } else { None };
+
+        Ok(Args {
+            target, config, check_config, watch, list_devices, dry_run, backend, calibrate, rebind, test, record, replay,
+            overlay, control, install, uninstall, poll, log_file, verbose,
+        })
+    }
+}
+
+fn parse_id(flag: &str, value: Option<String>) -> Result<u16, String> {
+    let value = value.ok_or_else(|| format!("{} requires a value", flag))?;
+    let parsed = match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+
+    parsed.map_err(|_| format!("Invalid {} {:?}", flag, value))
+}
+
+fn parse_backend(value: Option<String>) -> Result<Backend, String> {
+    match value.ok_or("--backend requires a value")?.as_str() {
+        "tfc" => Ok(Backend::Tfc),
+        "stdout" => Ok(Backend::Stdout),
+        #[cfg(target_os = "linux")]
+        "uinput" => Ok(Backend::Uinput),
+        #[cfg(not(target_os = "linux"))]
+        "uinput" => Err("The uinput backend is only available on Linux".to_string()),
+        #[cfg(all(target_os = "linux", feature = "virtual-pad"))]
+        "pad" => Ok(Backend::Pad),
+        #[cfg(not(all(target_os = "linux", feature = "virtual-pad")))]
+        "pad" => Err("The pad backend needs Linux and the virtual-pad feature".to_string()),
+        other => Err(format!("Invalid --backend {:?}, expected tfc, stdout, uinput or pad", other)),
+    }
+}
+
+fn parse_layout(value: Option<String>) -> Result<Layout, String> {
+    match value.ok_or("--layout requires a value")?.as_str() {
+        "horipad" => Ok(Layout::Horipad),
+        "generic" => Ok(Layout::Generic),
+        other => Err(format!("Invalid --layout {:?}, expected horipad or generic", other)),
+    }
+}
+
+fn parse_number<T: FromStr>(flag: &str, value: Option<String>) -> Result<T, String> {
+    let value = value.ok_or_else(|| format!("{} requires a value", flag))?;
+    value.parse().map_err(|_| format!("Invalid {} {:?}", flag, value))
+}
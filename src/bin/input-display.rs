@@ -0,0 +1,184 @@
+// A small always on top window drawing the live input, for streaming. It
+// reads the overlay feed of a running mac-usb-controller --overlay ADDR, so
+// it's a program of its own: macOS only lets a window run on the main
+// thread, and this way nothing it does can slow down dispatch.
+
+use std::env;
+use std::process;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use log::{debug, info, warn};
+use minifb::{Window, WindowOptions};
+
+use mac_usb_controller::overlay::Snapshot;
+
+const DEFAULT_ADDR: &str = "127.0.0.1:9001";
+
+const USAGE: &str = "usage: input-display [ADDR]
+
+Shows the input of a mac-usb-controller started with --overlay ADDR
+(default 127.0.0.1:9001) in an always on top window.";
+
+const WIDTH: usize = 360;
+const HEIGHT: usize = 180;
+
+const BACKGROUND: u32 = 0x20_20_20;
+const IDLE: u32 = 0x50_50_50;
+const PRESSED: u32 = 0x40_c0_ff;
+
+// Wait before looking for the controller's overlay again after losing it
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+// The boxes the sticks move in, x y w h, drawn lit while they're clicked
+const LSTICK: [usize; 4] = [20, 44, 60, 60];
+const RSTICK: [usize; 4] = [220, 100, 60, 60];
+
+// Where each button is drawn, x y w h
+const LAYOUT: [(&str, [usize; 4]); 17] = [
+    ("ZL", [20, 8, 60, 12]),
+    ("L", [20, 24, 60, 12]),
+    ("ZR", [280, 8, 60, 12]),
+    ("R", [280, 24, 60, 12]),
+    ("Minus", [130, 30, 20, 10]),
+    ("Plus", [210, 30, 20, 10]),
+    ("Home", [170, 48, 20, 20]),
+    ("Up", [50, 110, 18, 18]),
+    ("Down", [50, 146, 18, 18]),
+    ("Left", [32, 128, 18, 18]),
+    ("Right", [68, 128, 18, 18]),
+    ("X", [300, 44, 20, 20]),
+    ("Y", [276, 68, 20, 20]),
+    ("A", [324, 68, 20, 20]),
+    ("B", [300, 92, 20, 20]),
+    ("LSB", LSTICK),
+    ("RSB", RSTICK),
+];
+
+fn main() {
+    env_logger::init_from_env(env_logger::Env::default().filter_or(env_logger::DEFAULT_FILTER_ENV, "info"));
+
+    let addr = match env::args().nth(1) {
+        Some(arg) if arg == "-h" || arg == "--help" => {
+            println!("{}", USAGE);
+            return;
+        },
+        Some(addr) => addr,
+        None => DEFAULT_ADDR.to_string(),
+    };
+
+    let options = WindowOptions { topmost: true, ..WindowOptions::default() };
+    let mut window = match Window::new("mac-usb-controller", WIDTH, HEIGHT, options) {
+        Ok(window) => window,
+        Err(e) => {
+            eprintln!("Could not open a window: {}", e);
+            process::exit(1);
+        }
+    };
+    window.limit_update_rate(Some(Duration::from_millis(16)));
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || read_overlay(&addr, tx));
+
+    let mut buffer = vec![BACKGROUND; WIDTH * HEIGHT];
+    let mut snapshot: Option<Snapshot> = None;
+
+    while window.is_open() {
+        // only the newest matters, and it's only drawn when it changes
+        if let Some(latest) = rx.try_iter().last() {
+            if latest != snapshot {
+                let title = match &latest {
+                    Some(latest) => format!("mac-usb-controller - {}", latest.profile),
+                    None => "mac-usb-controller (not connected)".to_string(),
+                };
+                window.set_title(&title);
+                draw(&mut buffer, latest.as_ref());
+                snapshot = latest;
+            }
+        }
+
+        if let Err(e) = window.update_with_buffer(&buffer, WIDTH, HEIGHT) {
+            eprintln!("Could not draw the window: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+// Passes on every snapshot the overlay sends, and None whenever it's lost,
+// reconnecting until the window closes and takes the channel with it
+fn read_overlay(addr: &str, tx: mpsc::Sender<Option<Snapshot>>) {
+    let url = format!("ws://{}", addr);
+
+    loop {
+        match tungstenite::connect(url.as_str()) {
+            Ok((mut socket, _)) => {
+                info!("Connected to {}", url);
+
+                loop {
+                    let json = match socket.read_message() {
+                        Ok(tungstenite::Message::Text(json)) => json,
+                        Ok(_) => continue,
+                        Err(e) => {
+                            warn!("Lost {}: {}", url, e);
+                            break;
+                        }
+                    };
+
+                    match serde_json::from_str(&json) {
+                        Ok(snapshot) => if tx.send(Some(snapshot)).is_err() { return; },
+                        Err(e) => warn!("Ignoring overlay message {:?}: {}", json, e),
+                    }
+                }
+            },
+            Err(e) => debug!("Could not connect to {}: {}", url, e),
+        }
+
+        if tx.send(None).is_err() { return; }
+        thread::sleep(RETRY_DELAY);
+    }
+}
+
+fn draw(buffer: &mut [u32], snapshot: Option<&Snapshot>) {
+    buffer.iter_mut().for_each(|pixel| *pixel = BACKGROUND);
+
+    let held = |name: &str| snapshot.is_some_and(|snapshot| snapshot.buttons.iter().any(|b| b == name));
+
+    for &(name, [x, y, w, h]) in &LAYOUT {
+        let color = if held(name) { PRESSED } else { IDLE };
+        if name == "LSB" || name == "RSB" { outline(buffer, x, y, w, h, color); }
+        else { fill(buffer, x, y, w, h, color); }
+    }
+
+    let snapshot = match snapshot {
+        Some(snapshot) => snapshot,
+        None => return,
+    };
+
+    for &(stick, [x, y, w, h]) in &[(snapshot.lstick, LSTICK), (snapshot.rstick, RSTICK)] {
+        let dot_x = x + (stick[0] as usize * (w - 8)) / 255;
+        let dot_y = y + (stick[1] as usize * (h - 8)) / 255;
+        fill(buffer, dot_x, dot_y, 8, 8, PRESSED);
+    }
+
+    // analog mode only, a bar under each trigger filled by its pressure
+    if let Some([zl, zr]) = snapshot.triggers {
+        fill(buffer, 20, 2, zl as usize * 60 / 255, 4, PRESSED);
+        fill(buffer, 280, 2, zr as usize * 60 / 255, 4, PRESSED);
+    }
+}
+
+fn fill(buffer: &mut [u32], x: usize, y: usize, w: usize, h: usize, color: u32) {
+    for row in y..(y + h).min(HEIGHT) {
+        for col in x..(x + w).min(WIDTH) {
+            buffer[row * WIDTH + col] = color;
+        }
+    }
+}
+
+fn outline(buffer: &mut [u32], x: usize, y: usize, w: usize, h: usize, color: u32) {
+    fill(buffer, x, y, w, 2, color);
+    fill(buffer, x, y + h - 2, w, 2, color);
+    fill(buffer, x, y, 2, h, color);
+    fill(buffer, x + w - 2, y, 2, h, color);
+}
@@ -0,0 +1,131 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+// A bounded queue between one sender and one receiver. Unlike
+// std::sync::mpsc::sync_channel a full queue drops its oldest item
+// rather than blocking the sender, so a slow receiver can't stall reads.
+// Only items `droppable` says can go are ever dropped. With none of those
+// queued the queue grows past capacity instead, the rest being too few
+// for that to matter.
+pub fn bounded<T>(capacity: usize, droppable: fn(&T) -> bool) -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        inner: Mutex::new(Inner { items: VecDeque::with_capacity(capacity), closed: false }),
+        ready: Condvar::new(),
+    });
+
+    (Sender { shared: shared.clone(), capacity, droppable }, Receiver { shared })
+}
+
+struct Shared<T> {
+    inner: Mutex<Inner<T>>,
+    ready: Condvar,
+}
+
+struct Inner<T> {
+    items: VecDeque<T>,
+    // set once the sender is dropped
+    closed: bool,
+}
+
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+    capacity: usize,
+    droppable: fn(&T) -> bool,
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+#[derive(Debug)]
+pub enum RecvError {
+    Timeout,
+    Closed,
+}
+
+impl<T> Sender<T> {
+    // Returns false if the queue was full and the oldest droppable item
+    // had to go
+    pub fn send(&self, item: T) -> bool {
+        let mut inner = self.shared.inner.lock().unwrap();
+
+        let mut dropped = false;
+        if inner.items.len() >= self.capacity {
+            if let Some(oldest) = inner.items.iter().position(self.droppable) {
+                inner.items.remove(oldest);
+                dropped = true;
+            }
+        }
+        inner.items.push_back(item);
+
+        self.shared.ready.notify_one();
+        !dropped
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.shared.inner.lock().unwrap().closed = true;
+        self.shared.ready.notify_one();
+    }
+}
+
+impl<T> Receiver<T> {
+    // Anything still queued is handed out before Closed is returned
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvError> {
+        let deadline = Instant::now() + timeout;
+        let mut inner = self.shared.inner.lock().unwrap();
+
+        loop {
+            if let Some(item) = inner.items.pop_front() { return Ok(item); }
+            if inner.closed { return Err(RecvError::Closed); }
+
+            let now = Instant::now();
+            if now >= deadline { return Err(RecvError::Timeout); }
+
+            inner = self.shared.ready.wait_timeout(inner, deadline - now).unwrap().0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Negative numbers stand in for the reports that mustn't be dropped
+    fn droppable(item: &i32) -> bool {
+        *item >= 0
+    }
+
+    fn drain(rx: &Receiver<i32>) -> Vec<i32> {
+        let mut items = Vec::new();
+        while let Ok(item) = rx.recv_timeout(Duration::ZERO) { items.push(item); }
+        items
+    }
+
+    #[test]
+    fn full_queue_drops_the_oldest() {
+        let (tx, rx) = bounded(3, droppable);
+        assert!(tx.send(1) && tx.send(2) && tx.send(3));
+        assert!(!tx.send(4));
+        assert_eq!(drain(&rx), [2, 3, 4]);
+    }
+
+    #[test]
+    fn full_queue_keeps_what_cant_be_dropped() {
+        let (tx, rx) = bounded(3, droppable);
+        tx.send(1);
+        tx.send(-1);
+        for item in 2..10 { tx.send(item); }
+        assert_eq!(drain(&rx), [-1, 8, 9]);
+    }
+
+    #[test]
+    fn full_queue_of_what_cant_be_dropped_grows() {
+        let (tx, rx) = bounded(2, droppable);
+        assert!(tx.send(-1) && tx.send(-2) && tx.send(-3));
+        assert!(tx.send(1));
+        assert_eq!(drain(&rx), [-1, -2, -3, 1]);
+    }
+}
@@ -0,0 +1,1199 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::ops::{Index, IndexMut};
+use std::path::Path;
+use std::time::Duration;
+
+use serde::Deserialize;
+use tfc::{Enum, Key, MouseButton};
+
+use crate::keys::{key_name, parse_key};
+
+const DEFAULT_TURBO_RATE: f32 = 10.0;
+const DEFAULT_REPEAT_RATE: f32 = 30.0;
+const DEFAULT_TRIGGER_THRESHOLD: u8 = 128;
+const DEFAULT_HOLD_MS: u64 = 300;
+const DEFAULT_QUIT_HOLD_MS: u64 = 2000;
+const DEFAULT_DEBOUNCE_US: u64 = 5000;
+const DEFAULT_SEQUENCE_MS: u64 = 300;
+const DEFAULT_MOUSE_EXPONENT: f32 = 2.0;
+
+// How much slow mode scales the cursor and scrolling by, with turbo and
+// repeat slowed to match
+const DEFAULT_SLOW_FACTOR: f32 = 0.25;
+
+// Pixels the cursor moves per poll with the stick fully deflected.
+// At the ~1ms poll rate that works out to roughly 4000px/s.
+const DEFAULT_MOUSE_SPEED: f32 = 4.0;
+
+// Lines the right stick scrolls per scroll step fully deflected
+const DEFAULT_SCROLL_SPEED: u8 = 3;
+
+// How much faster scrolling gets per second the right stick is held, off by
+// default, and the most it can be sped up by
+const DEFAULT_SCROLL_RAMP: f32 = 0.0;
+const DEFAULT_SCROLL_RAMP_MAX: f32 = 4.0;
+
+// Radius (in axis steps out of 128) a stick must leave before it counts
+// as deflected, roughly 10% of full range
+const DEFAULT_DEADZONE: u8 = 13;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    turbo_rate: Option<f32>,
+    repeat_rate: Option<f32>,
+    socd: Socd,
+    dpad_mode: DpadMode,
+    mouse_curve: MouseCurve,
+    mouse_exponent: Option<f32>,
+    mouse_speed: Option<f32>,
+    mouse_smoothing: bool,
+    mouse_rate: Option<f32>,
+    normalize_diagonals: Option<bool>,
+    scroll_speed: Option<u8>,
+    scroll_ramp: Option<f32>,
+    scroll_ramp_max: Option<f32>,
+    deadzone: Option<u8>,
+    lstick_mode: Option<StickMode>,
+    rstick_mode: Option<StickMode>,
+    invert_lx: bool,
+    invert_ly: bool,
+    invert_rx: bool,
+    invert_ry: bool,
+    swap_sticks: bool,
+    focus_apps: Vec<String>,
+    trigger_threshold: Option<u8>,
+    hold_ms: Option<u64>,
+    quit_hold_ms: Option<u64>,
+    debounce_us: Option<u64>,
+    panic_buttons: Vec<String>,
+    slow_button: Option<String>,
+    slow_factor: Option<f32>,
+    buttons: Bindings,
+    profiles: Vec<ProfileFile>,
+    chords: Vec<ChordFile>,
+    stick_chords: Vec<StickChordFile>,
+    sequences: Vec<SequenceFile>,
+    // bit number (0-7) of the report's last byte to what it sends
+    unused_bits: BTreeMap<String, ActionsFile>,
+    // button to the button it's passed on as by --backend pad
+    pad_buttons: BTreeMap<String, String>,
+    calibration: Calibration,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ChordFile {
+    buttons: Vec<String>,
+    key: ActionsFile,
+    #[serde(default)]
+    priority: i32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StickChordFile {
+    click: String,
+    direction: String,
+    key: ActionsFile,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SequenceFile {
+    // button names, or several joined with + to be held together
+    steps: Vec<String>,
+    key: ActionsFile,
+    window_ms: Option<u64>,
+    #[serde(default)]
+    priority: i32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProfileFile {
+    name: String,
+    #[serde(default)]
+    buttons: Bindings,
+    #[serde(default)]
+    apps: Vec<String>,
+    #[serde(default)]
+    devices: Vec<String>,
+    // these fall back to the top level ones
+    mouse_speed: Option<f32>,
+    scroll_speed: Option<u8>,
+    deadzone: Option<u8>,
+    lstick_mode: Option<StickMode>,
+    rstick_mode: Option<StickMode>,
+    stick_chords: Option<Vec<StickChordFile>>,
+    sequences: Option<Vec<SequenceFile>>,
+}
+
+// Bindings for each logical button, anything left out keeps its default
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "PascalCase")]
+struct Bindings {
+    y: Option<BindingFile>,
+    b: Option<BindingFile>,
+    a: Option<BindingFile>,
+    x: Option<BindingFile>,
+    l: Option<BindingFile>,
+    r: Option<BindingFile>,
+    #[serde(rename = "ZL")]
+    zl: Option<BindingFile>,
+    #[serde(rename = "ZR")]
+    zr: Option<BindingFile>,
+    minus: Option<BindingFile>,
+    plus: Option<BindingFile>,
+    #[serde(rename = "LSB")]
+    lsb: Option<BindingFile>,
+    #[serde(rename = "RSB")]
+    rsb: Option<BindingFile>,
+    home: Option<BindingFile>,
+    up: Option<BindingFile>,
+    down: Option<BindingFile>,
+    left: Option<BindingFile>,
+    right: Option<BindingFile>,
+    up_left: Option<BindingFile>,
+    up_right: Option<BindingFile>,
+    down_left: Option<BindingFile>,
+    down_right: Option<BindingFile>,
+}
+
+// Either just the keys, or a table for the extra options
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum BindingFile {
+    Actions(ActionsFile),
+    Table(BindingTable),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BindingTable {
+    key: Option<ActionsFile>,
+    text: Option<String>,
+    #[serde(default)]
+    turbo: bool,
+    hold: Option<ActionsFile>,
+    #[serde(default)]
+    toggle: bool,
+    #[serde(default)]
+    repeat: bool,
+    trigger: Option<Trigger>,
+    // false leaves the button unbound but keeps the rest of the table
+    enabled: Option<bool>,
+}
+
+// A single key name, or a combo like ["ControlOrMeta", "Tab"] pressed in order
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ActionsFile {
+    One(String),
+    Combo(Vec<String>),
+}
+
+// Every logical button on the pad, in the order they're stored in a Mapping
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Button {
+    Y,
+    B,
+    A,
+    X,
+    L,
+    R,
+    ZL,
+    ZR,
+    Minus,
+    Plus,
+    LSB,
+    RSB,
+    Home,
+    Up,
+    Down,
+    Left,
+    Right,
+    // dpad diagonals, only sent when bound, otherwise they're the two
+    // cardinals they're made of
+    UpLeft,
+    UpRight,
+    DownLeft,
+    DownRight,
+}
+
+pub const BUTTON_COUNT: usize = 21;
+
+pub const BUTTONS: [Button; BUTTON_COUNT] = [
+    Button::Y, Button::B, Button::A, Button::X, Button::L, Button::R, Button::ZL, Button::ZR,
+    Button::Minus, Button::Plus, Button::LSB, Button::RSB, Button::Home,
+    Button::Up, Button::Down, Button::Left, Button::Right,
+    Button::UpLeft, Button::UpRight, Button::DownLeft, Button::DownRight,
+];
+
+// How opposite dpad directions held together are resolved
+#[derive(Debug, Default, Copy, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Socd {
+    // both are let go
+    #[default]
+    Neutral,
+    // whichever was pressed most recently wins
+    LastInput,
+    // up beats down, left and right cancel out
+    UpPriority,
+}
+
+// What the dpad's buttons stand for
+#[derive(Debug, Default, Copy, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DpadMode {
+    // Up, Down, Left and Right, with a diagonal being two of them unless
+    // it's bound itself
+    #[default]
+    Cardinals,
+    // each of the 8 positions is its own button, only ever one held
+    Octants,
+}
+
+// How stick deflection maps to cursor speed
+#[derive(Debug, Default, Copy, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MouseCurve {
+    // speed proportional to deflection
+    #[default]
+    Linear,
+    // slow near the center for fine aiming, ramping up to full speed at the edge
+    Exponential,
+    // deflection raised to mouse_exponent, above 1 is slower near the center
+    // and below 1 faster
+    Power,
+}
+
+// What a stick does, the left one moving the mouse and the right one
+// scrolling unless a profile says otherwise
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum StickMode {
+    Mouse,
+    // the dpad's bindings, each axis pressing its direction once it's pushed past halfway
+    Wasd,
+    Scroll,
+    Off,
+}
+
+// Something a button can hold down
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Action {
+    Key(Key),
+    Mouse(MouseButton),
+    // tapped once when pressed, there's nothing to hold
+    Media(Key),
+}
+
+// Spelled the way parse_action reads it
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::Key(key) => write!(f, "{}", key_name(*key)),
+            Action::Mouse(button) => write!(f, "Mouse{}", button.identifier_name()),
+            Action::Media(key) => write!(f, "{}", key_name(*key)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Binding {
+    // pressed in order on button down, released in reverse on button up
+    pub actions: Vec<Action>,
+    // repeatedly tap the actions while the button is held
+    pub turbo: bool,
+    // sent instead once the button is held for the hold time, making the
+    // actions above a tap on release
+    pub hold: Option<Vec<Action>>,
+    // one press holds the actions down and the next lets them go
+    pub toggle: bool,
+    // keep the keys down and send them down again every so often while the
+    // button is held, for apps that want more than the OS's own repeat
+    pub repeat: bool,
+    // typed out once per press, in place of any actions
+    pub text: Option<String>,
+    pub trigger: Trigger,
+}
+
+// When a button's plain actions or text are sent
+#[derive(Debug, Default, Copy, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Trigger {
+    // held down for as long as the button is
+    #[default]
+    OnPress,
+    // tapped once as the button is let go, for negative edge inputs
+    OnRelease,
+}
+
+impl Binding {
+    fn key(key: Key) -> Binding {
+        Binding { actions: vec![Action::Key(key)], turbo: false, hold: None, toggle: false, repeat: false, text: None, trigger: Trigger::OnPress }
+    }
+
+    fn unbound() -> Binding {
+        Binding { actions: Vec::new(), turbo: false, hold: None, toggle: false, repeat: false, text: None, trigger: Trigger::OnPress }
+    }
+
+    // Whether the button sends anything of its own
+    pub fn bound(&self) -> bool {
+        !self.actions.is_empty() || self.text.is_some()
+    }
+}
+
+// What each logical button does, indexed by Button
+#[derive(Debug, Clone)]
+pub struct Mapping {
+    bindings: [Binding; BUTTON_COUNT],
+}
+
+impl Index<Button> for Mapping {
+    type Output = Binding;
+
+    fn index(&self, button: Button) -> &Binding {
+        &self.bindings[button as usize]
+    }
+}
+
+impl IndexMut<Button> for Mapping {
+    fn index_mut(&mut self, button: Button) -> &mut Binding {
+        &mut self.bindings[button as usize]
+    }
+}
+
+impl Default for Mapping {
+    fn default() -> Mapping {
+        Mapping {
+            bindings: [
+                Binding::key(Key::P),
+                Binding::key(Key::O),
+                Binding::key(Key::I),
+                Binding::key(Key::U),
+                Binding::key(Key::Y),
+                Binding::key(Key::T),
+                Binding::key(Key::R),
+                Binding::key(Key::E),
+                Binding::key(Key::L),
+                Binding::key(Key::K),
+                Binding::key(Key::J),
+                Binding::key(Key::H),
+                Binding::key(Key::G),
+                Binding::key(Key::W),
+                Binding::key(Key::S),
+                Binding::key(Key::A),
+                Binding::key(Key::D),
+                Binding::unbound(),
+                Binding::unbound(),
+                Binding::unbound(),
+                Binding::unbound(),
+            ],
+        }
+    }
+}
+
+// Resting stick positions, measured by --calibrate
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Calibration {
+    pub lstick: [u8; 2],
+    pub rstick: [u8; 2],
+}
+
+impl Default for Calibration {
+    fn default() -> Calibration {
+        Calibration { lstick: [128, 128], rstick: [128, 128] }
+    }
+}
+
+// Written as the [calibration] table it's read back from
+impl fmt::Display for Calibration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "[calibration]")?;
+        writeln!(f, "lstick = [{}, {}]", self.lstick[0], self.lstick[1])?;
+        writeln!(f, "rstick = [{}, {}]", self.rstick[0], self.rstick[1])
+    }
+}
+
+// One action for each button --rebind was given, in the order they came
+#[derive(Debug, Clone, Default)]
+pub struct ButtonTable {
+    pub bindings: Vec<(Button, Action)>,
+}
+
+// Written as the [buttons] table it's read back from, Button's Debug being
+// the name that table knows it by
+impl fmt::Display for ButtonTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "[buttons]")?;
+        for (button, action) in &self.bindings {
+            writeln!(f, "{:?} = \"{}\"", button, action)?;
+        }
+        Ok(())
+    }
+}
+
+// Buttons that together tap their own actions instead of their usual ones
+#[derive(Debug, Clone)]
+pub struct Chord {
+    pub buttons: Vec<Button>,
+    pub actions: Vec<Action>,
+}
+
+// A stick clicked and pushed one way, tapping its own actions instead of
+// the click's and the stick's usual ones
+#[derive(Debug, Clone)]
+pub struct StickChord {
+    // LSB or RSB, the stick clicked is the one pushed
+    pub click: Button,
+    // Up, Down, Left or Right
+    pub direction: Button,
+    pub actions: Vec<Action>,
+}
+
+// Presses made in order within a window, tapping their own actions on
+// top of the buttons' usual ones
+#[derive(Debug, Clone)]
+pub struct Sequence {
+    // each step's buttons, all held once it's pressed. The dpad counts by
+    // position, so Right is only right and not a diagonal with it.
+    pub steps: Vec<Vec<Button>>,
+    // longest from the first step's press to the last one's
+    pub window: Duration,
+    pub actions: Vec<Action>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Profile {
+    pub name: String,
+    pub mapping: Mapping,
+    // bundle ids of the applications this profile is picked for
+    pub apps: Vec<String>,
+    // serial numbers or paths of the controllers that start on this profile
+    pub devices: Vec<String>,
+    // pixels per poll at full deflection
+    pub mouse_speed: f32,
+    // lines per scroll step at full deflection
+    pub scroll_speed: i32,
+    // out of 128
+    pub deadzone: i32,
+    pub lstick_mode: StickMode,
+    pub rstick_mode: StickMode,
+    // first listed wins if several are entered at once
+    pub stick_chords: Vec<StickChord>,
+    // highest priority first
+    pub sequences: Vec<Sequence>,
+}
+
+impl Default for Profile {
+    fn default() -> Profile {
+        Profile {
+            name: "default".to_string(),
+            mapping: Mapping::default(),
+            apps: Vec::new(),
+            devices: Vec::new(),
+            mouse_speed: DEFAULT_MOUSE_SPEED,
+            scroll_speed: DEFAULT_SCROLL_SPEED as i32,
+            deadzone: DEFAULT_DEADZONE as i32,
+            lstick_mode: StickMode::Mouse,
+            rstick_mode: StickMode::Scroll,
+            stick_chords: Vec::new(),
+            sequences: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Config {
+    pub profiles: Vec<Profile>,
+    // turbo presses per second
+    pub turbo_rate: f32,
+    // times per second a repeat binding's keys are sent down again
+    pub repeat_rate: f32,
+    pub socd: Socd,
+    pub dpad_mode: DpadMode,
+    pub mouse_curve: MouseCurve,
+    // for MouseCurve::Power
+    pub mouse_exponent: f32,
+    // carry fractions of a pixel over to the next poll instead of dropping them
+    pub mouse_smoothing: bool,
+    // times per second the cursor moves and scrolling steps, None for once
+    // per report
+    pub mouse_rate: Option<f32>,
+    // cap the cursor's speed on diagonals at what it is on straight lines,
+    // instead of each axis going full speed
+    pub normalize_diagonals: bool,
+    // added to the scroll speed's multiplier for each second the right stick
+    // is held the same way, up to scroll_ramp_max. 0 keeps it constant.
+    pub scroll_ramp: f32,
+    pub scroll_ramp_max: f32,
+    // analog trigger pressure (1-255) that counts as a press
+    pub trigger_threshold: u8,
+    // how long a button with a hold action has to be held for it
+    pub hold_time: Duration,
+    // how long holding Home takes to quit, None if it never does
+    pub quit_hold: Option<Duration>,
+    // how long after a button changes before another change of it counts,
+    // None to take every change
+    pub debounce: Option<Duration>,
+    // highest priority first
+    pub chords: Vec<Chord>,
+    // held together these let go of everything, empty if nothing does
+    pub panic_buttons: Vec<Button>,
+    // pressed, turns slow mode on or off, None if nothing does
+    pub slow_button: Option<Button>,
+    pub slow_factor: f32,
+    pub calibration: Calibration,
+    // flip each physical axis, lx ly rx ry
+    pub invert: [bool; 4],
+    // the sticks trade jobs
+    pub swap_sticks: bool,
+    // bundle ids of the apps events are meant for, empty to send them
+    // whatever is focused
+    pub focus_apps: Vec<String>,
+    // what each bit of the report's last byte sends, lowest first. The
+    // known pads leave it at 0, some variants put extra buttons there.
+    pub unused_bits: [Vec<Action>; 8],
+    // for --backend pad, each button listed passed on as the other
+    pub pad_buttons: Vec<(Button, Button)>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            profiles: vec![Profile::default()],
+            turbo_rate: DEFAULT_TURBO_RATE,
+            repeat_rate: DEFAULT_REPEAT_RATE,
+            socd: Socd::default(),
+            dpad_mode: DpadMode::default(),
+            mouse_curve: MouseCurve::default(),
+            mouse_exponent: DEFAULT_MOUSE_EXPONENT,
+            mouse_smoothing: false,
+            mouse_rate: None,
+            normalize_diagonals: true,
+            scroll_ramp: DEFAULT_SCROLL_RAMP,
+            scroll_ramp_max: DEFAULT_SCROLL_RAMP_MAX,
+            trigger_threshold: DEFAULT_TRIGGER_THRESHOLD,
+            hold_time: Duration::from_millis(DEFAULT_HOLD_MS),
+            quit_hold: Some(Duration::from_millis(DEFAULT_QUIT_HOLD_MS)),
+            debounce: Some(Duration::from_micros(DEFAULT_DEBOUNCE_US)),
+            chords: Vec::new(),
+            panic_buttons: Vec::new(),
+            slow_button: None,
+            slow_factor: DEFAULT_SLOW_FACTOR,
+            calibration: Calibration::default(),
+            invert: [false; 4],
+            swap_sticks: false,
+            focus_apps: Vec::new(),
+            unused_bits: Default::default(),
+            pad_buttons: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    // Index of the profile listing the device, or the first one
+    pub fn profile_for_device(&self, id: &str) -> usize {
+        self.profiles.iter()
+            .position(|profile| profile.devices.iter().any(|device| device == id))
+            .unwrap_or(0)
+    }
+
+    // Whether events should go out while app is focused. An unknown app
+    // only gets them if no focus_apps were given.
+    pub fn sends_to(&self, app: Option<&str>) -> bool {
+        self.focus_apps.is_empty() || app.is_some_and(|app| self.focus_apps.iter().any(|a| a == app))
+    }
+
+    pub fn watches_focus(&self) -> bool {
+        !self.focus_apps.is_empty() || self.profiles.iter().any(|profile| !profile.apps.is_empty())
+    }
+
+    // Things that load fine but are probably not what was meant, for
+    // --check-config. None of them stop the config being used.
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        let button_set = |buttons: &[Button]| buttons.iter().fold(0u32, |set, &b| set | 1 << b as usize);
+
+        if self.dpad_mode == DpadMode::Octants && self.socd != Socd::Neutral {
+            warnings.push("socd does nothing with dpad_mode = \"octants\", the dpad can only be in one position".to_string());
+        }
+
+        for (i, chord) in self.chords.iter().enumerate() {
+            if let Some(j) = self.chords[..i].iter().position(|other| button_set(&other.buttons) == button_set(&chord.buttons)) {
+                warnings.push(format!("chords {} and {} use the same buttons, only chord {} ever fires", j, i, j));
+            }
+        }
+
+        if let Some(i) = self.chords.iter().position(|chord| button_set(&chord.buttons) == button_set(&self.panic_buttons)) {
+            warnings.push(format!("chord {} uses the panic_buttons, it never fires", i));
+        }
+
+        if let Some(slow) = self.slow_button {
+            if let Some(i) = self.chords.iter().position(|chord| chord.buttons.contains(&slow)) {
+                warnings.push(format!("chord {} uses the slow_button, it never fires", i));
+            }
+        }
+
+        for (i, profile) in self.profiles.iter().enumerate() {
+            if self.profiles[..i].iter().any(|other| other.name == profile.name) {
+                warnings.push(format!("there's more than one profile named {:?}", profile.name));
+            }
+
+            for device in &profile.devices {
+                if let Some(other) = self.profiles[..i].iter().find(|other| other.devices.contains(device)) {
+                    warnings.push(format!("device {:?} is listed by profiles {:?} and {:?}, it starts on {:?}",
+                        device, other.name, profile.name, other.name));
+                }
+            }
+
+            // toggles holding the same key keep it down until both are off
+            let toggles: Vec<Button> = BUTTONS.iter().copied().filter(|&b| profile.mapping[b].toggle).collect();
+            for (j, &a) in toggles.iter().enumerate() {
+                for &b in &toggles[j + 1..] {
+                    if profile.mapping[a].actions.iter().any(|action| profile.mapping[b].actions.contains(action)) {
+                        warnings.push(format!("profile {:?}: {:?} and {:?} toggle the same key, it stays down until both are toggled off",
+                            profile.name, a, b));
+                    }
+                }
+            }
+
+            for (j, chord) in profile.stick_chords.iter().enumerate() {
+                let same = |other: &StickChord| other.click == chord.click && other.direction == chord.direction;
+                if let Some(k) = profile.stick_chords[..j].iter().position(same) {
+                    warnings.push(format!("profile {:?}: stick chords {} and {} are both {:?} {:?}, only stick chord {} ever fires",
+                        profile.name, k, j, chord.click, chord.direction, k));
+                }
+            }
+        }
+
+        warnings
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+    UnknownKey { name: String, line: Option<usize> },
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "Could not read config: {}", e),
+            ConfigError::Parse(e) => write!(f, "Invalid config: {}", e),
+            ConfigError::UnknownKey { name, line: Some(line) } => write!(f, "Unknown key {:?} on line {}", name, line),
+            ConfigError::UnknownKey { name, line: None } => write!(f, "Unknown key {:?}", name),
+            ConfigError::Invalid(message) => write!(f, "Invalid config: {}", message),
+        }
+    }
+}
+
+pub fn load(path: &Path) -> Result<Config, ConfigError> {
+    let source = fs::read_to_string(path).map_err(ConfigError::Io)?;
+    parse(&source)
+}
+
+// Replaces the [calibration] table in the config at path, or adds one. The
+// rest of the file is kept as written, comments and all.
+pub fn save_calibration(path: &Path, calibration: &Calibration) -> io::Result<()> {
+    save_table(path, "[calibration]", &calibration.to_string())
+}
+
+// Same for the [buttons] table --rebind builds, so any button bound there
+// before and not rebound goes back to its default
+pub fn save_buttons(path: &Path, buttons: &ButtonTable) -> io::Result<()> {
+    save_table(path, "[buttons]", &buttons.to_string())
+}
+
+// `table` is written out whole, header included
+fn save_table(path: &Path, header: &str, table: &str) -> io::Result<()> {
+    let source = match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+
+    let lines: Vec<&str> = source.lines().collect();
+
+    let updated = match lines.iter().position(|line| line.trim() == header) {
+        Some(start) => {
+            let end = lines[start + 1..].iter()
+                .position(|line| line.trim_start().starts_with('['))
+                .map_or(lines.len(), |i| start + 1 + i);
+
+            let mut updated = lines[..start].join("\n");
+            if start > 0 { updated.push('\n'); }
+            updated.push_str(table);
+            if end < lines.len() {
+                updated.push('\n');
+                updated.push_str(&lines[end..].join("\n"));
+                updated.push('\n');
+            }
+            updated
+        },
+        None if source.trim().is_empty() => table.to_string(),
+        None => format!("{}\n\n{}", source.trim_end(), table),
+    };
+
+    fs::write(path, updated)
+}
+
+// Top level [buttons] apply to every profile, and each profile's own
+// buttons override those. Without any [[profiles]] the top level
+// buttons become a single "default" profile.
+pub fn parse(source: &str) -> Result<Config, ConfigError> {
+    let file: ConfigFile = toml::from_str(source).map_err(ConfigError::Parse)?;
+    let base = file.buttons.resolve(&Mapping::default(), source)?;
+
+    let turbo_rate = file.turbo_rate.unwrap_or(DEFAULT_TURBO_RATE);
+    if !turbo_rate.is_finite() || turbo_rate <= 0.0 {
+        return Err(ConfigError::Invalid(format!("turbo_rate must be positive, got {}", turbo_rate)));
+    }
+
+    let repeat_rate = file.repeat_rate.unwrap_or(DEFAULT_REPEAT_RATE);
+    if !repeat_rate.is_finite() || repeat_rate <= 0.0 {
+        return Err(ConfigError::Invalid(format!("repeat_rate must be positive, got {}", repeat_rate)));
+    }
+
+    let mouse_rate = file.mouse_rate;
+    if let Some(rate) = mouse_rate {
+        if !rate.is_finite() || rate <= 0.0 {
+            return Err(ConfigError::Invalid(format!("mouse_rate must be positive, got {}", rate)));
+        }
+    }
+
+    let trigger_threshold = file.trigger_threshold.unwrap_or(DEFAULT_TRIGGER_THRESHOLD);
+    if trigger_threshold == 0 {
+        return Err(ConfigError::Invalid("trigger_threshold must be at least 1".to_string()));
+    }
+
+    // a zero exponent would move at full speed from the center
+    let mouse_exponent = file.mouse_exponent.unwrap_or(DEFAULT_MOUSE_EXPONENT);
+    if !mouse_exponent.is_finite() || mouse_exponent <= 0.0 {
+        return Err(ConfigError::Invalid(format!("mouse_exponent must be positive, got {}", mouse_exponent)));
+    }
+
+    let scroll_ramp = file.scroll_ramp.unwrap_or(DEFAULT_SCROLL_RAMP);
+    if !scroll_ramp.is_finite() || scroll_ramp < 0.0 {
+        return Err(ConfigError::Invalid(format!("scroll_ramp can't be negative, got {}", scroll_ramp)));
+    }
+
+    // below 1 the ramp would slow scrolling down instead
+    let scroll_ramp_max = file.scroll_ramp_max.unwrap_or(DEFAULT_SCROLL_RAMP_MAX);
+    if !scroll_ramp_max.is_finite() || scroll_ramp_max < 1.0 {
+        return Err(ConfigError::Invalid(format!("scroll_ramp_max must be at least 1, got {}", scroll_ramp_max)));
+    }
+
+    let mouse_speed = check_mouse_speed(file.mouse_speed.unwrap_or(DEFAULT_MOUSE_SPEED))?;
+    let scroll_speed = check_scroll_speed(file.scroll_speed.unwrap_or(DEFAULT_SCROLL_SPEED))?;
+    let deadzone = check_deadzone(file.deadzone.unwrap_or(DEFAULT_DEADZONE))?;
+    let lstick_mode = file.lstick_mode.unwrap_or(StickMode::Mouse);
+    let rstick_mode = file.rstick_mode.unwrap_or(StickMode::Scroll);
+
+    let hold_time = Duration::from_millis(file.hold_ms.unwrap_or(DEFAULT_HOLD_MS));
+
+    // 0 turns quitting from the controller off
+    let quit_hold = match file.quit_hold_ms.unwrap_or(DEFAULT_QUIT_HOLD_MS) {
+        0 => None,
+        ms => Some(Duration::from_millis(ms)),
+    };
+
+    // and 0 turns debouncing off
+    let debounce = match file.debounce_us.unwrap_or(DEFAULT_DEBOUNCE_US) {
+        0 => None,
+        us => Some(Duration::from_micros(us)),
+    };
+
+    // sort_by_key is stable, so equal priorities keep their order in the file
+    let mut chords = file.chords.iter()
+        .map(|chord| Ok((chord.priority, chord.resolve(source)?)))
+        .collect::<Result<Vec<_>, ConfigError>>()?;
+    chords.sort_by_key(|(priority, _)| -priority);
+    let chords = chords.into_iter().map(|(_, chord)| chord).collect();
+
+    // empty turns it off
+    let panic_buttons = if file.panic_buttons.is_empty() { Vec::new() } else { chord_buttons(&file.panic_buttons, "panic combo")? };
+
+    // It's taken out of every report, so it never sends its own binding.
+    // The dpad isn't a bit that can be taken out, and Home would never quit.
+    let slow_button = file.slow_button.as_deref()
+        .map(|name| match parse_button(name) {
+            Some(Button::Home) => Err(ConfigError::Invalid("slow_button can't be Home, holding it could never quit".to_string())),
+            Some(Button::Up) | Some(Button::Down) | Some(Button::Left) | Some(Button::Right)
+            | Some(Button::UpLeft) | Some(Button::UpRight) | Some(Button::DownLeft) | Some(Button::DownRight) => {
+                Err(ConfigError::Invalid(format!("dpad button {:?} can't be the slow_button", name)))
+            },
+            Some(button) => Ok(button),
+            None => Err(ConfigError::Invalid(format!("unknown slow_button {:?}", name))),
+        })
+        .transpose()?;
+
+    let slow_factor = file.slow_factor.unwrap_or(DEFAULT_SLOW_FACTOR);
+    if !slow_factor.is_finite() || slow_factor <= 0.0 || slow_factor > 1.0 {
+        return Err(ConfigError::Invalid(format!("slow_factor must be more than 0 and at most 1, got {}", slow_factor)));
+    }
+
+    let stick_chords = resolve_stick_chords(&file.stick_chords, source)?;
+    let sequences = resolve_sequences(&file.sequences, source)?;
+
+    let mut unused_bits: [Vec<Action>; 8] = Default::default();
+    for (bit, actions) in &file.unused_bits {
+        let i = match bit.parse::<usize>() {
+            Ok(i) if i < 8 => i,
+            _ => return Err(ConfigError::Invalid(format!("unused_bits are numbered 0 to 7, not {:?}", bit))),
+        };
+        unused_bits[i] = actions.resolve(source)?;
+    }
+
+    // the dpad is the gamepad's hat, there's no button to send it as
+    let pad_button = |name: &String| match parse_button(name) {
+        Some(Button::Up) | Some(Button::Down) | Some(Button::Left) | Some(Button::Right)
+        | Some(Button::UpLeft) | Some(Button::UpRight) | Some(Button::DownLeft) | Some(Button::DownRight) => {
+            Err(ConfigError::Invalid(format!("dpad button {:?} can't be in pad_buttons", name)))
+        },
+        Some(button) => Ok(button),
+        None => Err(ConfigError::Invalid(format!("unknown button {:?} in pad_buttons", name))),
+    };
+    let pad_buttons = file.pad_buttons.iter()
+        .map(|(from, to)| Ok((pad_button(from)?, pad_button(to)?)))
+        .collect::<Result<Vec<_>, ConfigError>>()?;
+
+    let profiles = if file.profiles.is_empty() {
+        vec![Profile { mapping: base, mouse_speed, scroll_speed, deadzone, lstick_mode, rstick_mode, stick_chords, sequences, ..Profile::default() }]
+    } else {
+        file.profiles.iter()
+            .map(|profile| Ok(Profile {
+                name: profile.name.clone(),
+                mapping: profile.buttons.resolve(&base, source)?,
+                apps: profile.apps.clone(),
+                devices: profile.devices.clone(),
+                mouse_speed: profile.mouse_speed.map_or(Ok(mouse_speed), check_mouse_speed)?,
+                scroll_speed: profile.scroll_speed.map_or(Ok(scroll_speed), check_scroll_speed)?,
+                deadzone: profile.deadzone.map_or(Ok(deadzone), check_deadzone)?,
+                lstick_mode: profile.lstick_mode.unwrap_or(lstick_mode),
+                rstick_mode: profile.rstick_mode.unwrap_or(rstick_mode),
+                stick_chords: match &profile.stick_chords {
+                    Some(chords) => resolve_stick_chords(chords, source)?,
+                    None => stick_chords.clone(),
+                },
+                sequences: match &profile.sequences {
+                    Some(sequences) => resolve_sequences(sequences, source)?,
+                    None => sequences.clone(),
+                },
+            }))
+            .collect::<Result<_, ConfigError>>()?
+    };
+
+    Ok(Config {
+        profiles,
+        turbo_rate,
+        repeat_rate,
+        socd: file.socd,
+        dpad_mode: file.dpad_mode,
+        mouse_curve: file.mouse_curve,
+        mouse_exponent,
+        mouse_smoothing: file.mouse_smoothing,
+        mouse_rate,
+        normalize_diagonals: file.normalize_diagonals.unwrap_or(true),
+        scroll_ramp,
+        scroll_ramp_max,
+        trigger_threshold,
+        hold_time,
+        quit_hold,
+        debounce,
+        chords,
+        panic_buttons,
+        slow_button,
+        slow_factor,
+        calibration: file.calibration,
+        invert: [file.invert_lx, file.invert_ly, file.invert_rx, file.invert_ry],
+        swap_sticks: file.swap_sticks,
+        focus_apps: file.focus_apps,
+        unused_bits,
+        pad_buttons,
+    })
+}
+
+// The keys tfc sends as system media events rather than key presses
+const MEDIA_KEYS: [Key; 6] = [Key::PlayPause, Key::VolumeUp, Key::VolumeDown, Key::Mute, Key::FastForward, Key::Rewind];
+
+// Media keys people reach for that tfc has no way to send
+const UNSUPPORTED_MEDIA_KEYS: [&str; 4] = ["NextTrack", "PreviousTrack", "Stop", "Eject"];
+
+// Same spelling as the [buttons] table
+pub fn parse_button(name: &str) -> Option<Button> {
+    match name {
+        "Y" => Some(Button::Y),
+        "B" => Some(Button::B),
+        "A" => Some(Button::A),
+        "X" => Some(Button::X),
+        "L" => Some(Button::L),
+        "R" => Some(Button::R),
+        "ZL" => Some(Button::ZL),
+        "ZR" => Some(Button::ZR),
+        "Minus" => Some(Button::Minus),
+        "Plus" => Some(Button::Plus),
+        "LSB" => Some(Button::LSB),
+        "RSB" => Some(Button::RSB),
+        "Home" => Some(Button::Home),
+        "Up" => Some(Button::Up),
+        "Down" => Some(Button::Down),
+        "Left" => Some(Button::Left),
+        "Right" => Some(Button::Right),
+        "UpLeft" => Some(Button::UpLeft),
+        "UpRight" => Some(Button::UpRight),
+        "DownLeft" => Some(Button::DownLeft),
+        "DownRight" => Some(Button::DownRight),
+        _ => None,
+    }
+}
+
+// Mouse buttons are spelled "MouseLeft", "MouseRight" and "MouseMiddle",
+// anything else is a key name, see keys::parse_key
+pub fn parse_action(name: &str) -> Option<Action> {
+    let mouse = name.strip_prefix("Mouse")
+        .and_then(|button| MouseButton::iter().find(|b| b.identifier_name() == button));
+
+    match mouse {
+        Some(button) => Some(Action::Mouse(button)),
+        None => parse_key(name).ok().map(|key| if MEDIA_KEYS.contains(&key) { Action::Media(key) } else { Action::Key(key) }),
+    }
+}
+
+impl Bindings {
+    fn resolve(&self, default: &Mapping, source: &str) -> Result<Mapping, ConfigError> {
+        let bind = |binding: &Option<BindingFile>, button: Button| -> Result<Binding, ConfigError> {
+            match binding {
+                None => Ok(default[button].clone()),
+                Some(BindingFile::Actions(actions)) => Ok(Binding { actions: actions.resolve(source)?, turbo: false, hold: None, toggle: false, repeat: false, text: None, trigger: Trigger::OnPress }),
+                Some(BindingFile::Table(table)) => table.resolve(button, source),
+            }
+        };
+
+        Ok(Mapping {
+            bindings: [
+                bind(&self.y, Button::Y)?,
+                bind(&self.b, Button::B)?,
+                bind(&self.a, Button::A)?,
+                bind(&self.x, Button::X)?,
+                bind(&self.l, Button::L)?,
+                bind(&self.r, Button::R)?,
+                bind(&self.zl, Button::ZL)?,
+                bind(&self.zr, Button::ZR)?,
+                bind(&self.minus, Button::Minus)?,
+                bind(&self.plus, Button::Plus)?,
+                bind(&self.lsb, Button::LSB)?,
+                bind(&self.rsb, Button::RSB)?,
+                bind(&self.home, Button::Home)?,
+                bind(&self.up, Button::Up)?,
+                bind(&self.down, Button::Down)?,
+                bind(&self.left, Button::Left)?,
+                bind(&self.right, Button::Right)?,
+                bind(&self.up_left, Button::UpLeft)?,
+                bind(&self.up_right, Button::UpRight)?,
+                bind(&self.down_left, Button::DownLeft)?,
+                bind(&self.down_right, Button::DownRight)?,
+            ],
+        })
+    }
+}
+
+impl BindingTable {
+    // turbo, hold, toggle and repeat each change what a press does, so at
+    // most one can be set, and firing on release makes a press do nothing
+    // so none of them go with it. A button types text or sends keys, never
+    // both, and text can't be held so the modes don't apply. A disabled binding
+    // still has to be valid, so turning it back on can't break the config.
+    fn resolve(&self, button: Button, source: &str) -> Result<Binding, ConfigError> {
+        let modes = [self.turbo, self.hold.is_some(), self.toggle, self.repeat].iter().filter(|&&set| set).count();
+        if modes > 1 {
+            return Err(ConfigError::Invalid(format!("{:?} can only have one of turbo, hold, toggle and repeat", button)));
+        }
+        let trigger = self.trigger.unwrap_or_default();
+        if trigger == Trigger::OnRelease && modes > 0 {
+            return Err(ConfigError::Invalid(format!("{:?} fires on release, so it can't use turbo, hold, toggle or repeat", button)));
+        }
+
+        let actions = match (&self.key, &self.text) {
+            (Some(key), None) => key.resolve(source)?,
+            (None, Some(text)) if text.is_empty() => {
+                return Err(ConfigError::Invalid(format!("{:?} can't type empty text", button)));
+            },
+            (None, Some(_)) if modes > 0 => {
+                return Err(ConfigError::Invalid(format!("{:?} types text, so it can't use turbo, hold, toggle or repeat", button)));
+            },
+            (None, Some(_)) => Vec::new(),
+            _ => return Err(ConfigError::Invalid(format!("{:?} needs exactly one of key and text", button))),
+        };
+        let hold = self.hold.as_ref().map(|hold| hold.resolve(source)).transpose()?;
+
+        if self.enabled == Some(false) { return Ok(Binding::unbound()); }
+
+        Ok(Binding {
+            actions,
+            turbo: self.turbo,
+            hold,
+            toggle: self.toggle,
+            repeat: self.repeat,
+            text: self.text.clone(),
+            trigger,
+        })
+    }
+}
+
+impl ChordFile {
+    fn resolve(&self, source: &str) -> Result<Chord, ConfigError> {
+        let buttons = chord_buttons(&self.buttons, "chord")?;
+        Ok(Chord { buttons, actions: self.key.resolve(source)? })
+    }
+}
+
+// The dpad reports a direction rather than separate buttons, so only the
+// face, shoulder and extra buttons can be held together like this. `what`
+// names what they're for in errors.
+fn chord_buttons(names: &[String], what: &str) -> Result<Vec<Button>, ConfigError> {
+    let buttons = names.iter()
+        .map(|name| match parse_button(name) {
+            Some(Button::Up) | Some(Button::Down) | Some(Button::Left) | Some(Button::Right)
+            | Some(Button::UpLeft) | Some(Button::UpRight) | Some(Button::DownLeft) | Some(Button::DownRight) => {
+                Err(ConfigError::Invalid(format!("dpad button {:?} can't be part of a {}", name, what)))
+            },
+            Some(button) => Ok(button),
+            None => Err(ConfigError::Invalid(format!("unknown {} button {:?}", what, name))),
+        })
+        .collect::<Result<Vec<_>, ConfigError>>()?;
+
+    if buttons.len() < 2 {
+        return Err(ConfigError::Invalid(format!("a {} needs at least two buttons", what)));
+    }
+
+    Ok(buttons)
+}
+
+impl StickChordFile {
+    fn resolve(&self, source: &str) -> Result<StickChord, ConfigError> {
+        let click = match parse_button(&self.click) {
+            Some(button @ Button::LSB) | Some(button @ Button::RSB) => button,
+            _ => return Err(ConfigError::Invalid(format!("stick chords click LSB or RSB, not {:?}", self.click))),
+        };
+
+        let direction = match parse_button(&self.direction) {
+            Some(button @ Button::Up) | Some(button @ Button::Down) | Some(button @ Button::Left) | Some(button @ Button::Right) => button,
+            _ => return Err(ConfigError::Invalid(format!("stick chord direction must be Up, Down, Left or Right, not {:?}", self.direction))),
+        };
+
+        Ok(StickChord { click, direction, actions: self.key.resolve(source)? })
+    }
+}
+
+fn resolve_stick_chords(chords: &[StickChordFile], source: &str) -> Result<Vec<StickChord>, ConfigError> {
+    chords.iter().map(|chord| chord.resolve(source)).collect()
+}
+
+impl SequenceFile {
+    fn resolve(&self, source: &str) -> Result<Sequence, ConfigError> {
+        let steps = self.steps.iter()
+            .map(|step| step.split('+')
+                .map(|name| parse_button(name.trim()).ok_or_else(|| ConfigError::Invalid(format!("unknown sequence button {:?}", name.trim()))))
+                .collect::<Result<Vec<_>, ConfigError>>())
+            .collect::<Result<Vec<_>, ConfigError>>()?;
+
+        if steps.len() < 2 {
+            return Err(ConfigError::Invalid("sequences need at least two steps".to_string()));
+        }
+
+        let window = match self.window_ms.unwrap_or(DEFAULT_SEQUENCE_MS) {
+            0 => return Err(ConfigError::Invalid("sequence window_ms must be at least 1".to_string())),
+            ms => Duration::from_millis(ms),
+        };
+
+        Ok(Sequence { steps, window, actions: self.key.resolve(source)? })
+    }
+}
+
+// sort_by_key is stable, so equal priorities keep their order in the file
+fn resolve_sequences(sequences: &[SequenceFile], source: &str) -> Result<Vec<Sequence>, ConfigError> {
+    let mut sequences = sequences.iter()
+        .map(|sequence| Ok((sequence.priority, sequence.resolve(source)?)))
+        .collect::<Result<Vec<_>, ConfigError>>()?;
+    sequences.sort_by_key(|(priority, _)| -priority);
+    Ok(sequences.into_iter().map(|(_, sequence)| sequence).collect())
+}
+
+impl ActionsFile {
+    fn resolve(&self, source: &str) -> Result<Vec<Action>, ConfigError> {
+        match self {
+            ActionsFile::One(name) => Ok(vec![resolve_action(name, source)?]),
+            ActionsFile::Combo(names) if names.is_empty() => Err(ConfigError::Invalid("key combos can't be empty".to_string())),
+            ActionsFile::Combo(names) => names.iter().map(|name| resolve_action(name, source)).collect(),
+        }
+    }
+}
+
+fn check_mouse_speed(mouse_speed: f32) -> Result<f32, ConfigError> {
+    if !mouse_speed.is_finite() || mouse_speed <= 0.0 {
+        return Err(ConfigError::Invalid(format!("mouse_speed must be positive, got {}", mouse_speed)));
+    }
+    Ok(mouse_speed)
+}
+
+fn check_scroll_speed(scroll_speed: u8) -> Result<i32, ConfigError> {
+    if scroll_speed == 0 {
+        return Err(ConfigError::Invalid("scroll_speed must be at least 1".to_string()));
+    }
+    Ok(scroll_speed as i32)
+}
+
+// at 128 or more the stick could never leave it
+fn check_deadzone(deadzone: u8) -> Result<i32, ConfigError> {
+    if deadzone >= 128 {
+        return Err(ConfigError::Invalid(format!("deadzone must be less than 128, got {}", deadzone)));
+    }
+    Ok(deadzone as i32)
+}
+
+fn resolve_action(name: &str, source: &str) -> Result<Action, ConfigError> {
+    if UNSUPPORTED_MEDIA_KEYS.iter().any(|key| key.eq_ignore_ascii_case(name)) {
+        let supported: Vec<&str> = MEDIA_KEYS.iter().map(|key| key.identifier_name()).collect();
+        let line = find_line(source, name).map_or(String::new(), |line| format!(" on line {}", line));
+        return Err(ConfigError::Invalid(format!("media key {:?}{} can't be sent, the media keys are {}", name, line, supported.join(", "))));
+    }
+
+    parse_action(name).ok_or_else(|| ConfigError::UnknownKey {
+        name: name.to_string(),
+        line: find_line(source, name),
+    })
+}
+
+// serde has already thrown away positions by the time keys are resolved,
+// so find the first line quoting the bad name for the error message
+fn find_line(source: &str, name: &str) -> Option<usize> {
+    let quoted = format!("\"{}\"", name);
+    source.lines().position(|line| line.contains(&quoted)).map(|i| i + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unsupported_media_keys_in_any_case() {
+        for name in &["NextTrack", "nexttrack", "EJECT", "stop"] {
+            match resolve_action(name, "") {
+                Err(ConfigError::Invalid(message)) => assert!(message.starts_with("media key"), "{}", message),
+                other => panic!("{} gave {:?}", name, other),
+            }
+        }
+        assert_eq!(resolve_action("playpause", "").ok(), Some(Action::Media(Key::PlayPause)));
+    }
+}
@@ -0,0 +1,247 @@
+// --control, a Unix socket scripts can drive the running agent through.
+// Each line sent is one command and gets back any lines of output and then
+// "ok", or a single "error: ..." line:
+//
+//   profile NAME   switch every controller to the profile named NAME, or
+//                  the NAME'th one counting from 1
+//   reload         load the config again, like saving it with --watch
+//   state          one line per controller, its profile and what it's holding
+//   release-all    let go of everything, as if the panic_buttons were pressed
+
+use std::sync::mpsc;
+
+use crate::config::Config;
+
+pub enum Command {
+    Profile(String),
+    // already loaded and checked, so a bad config never gets to dispatch
+    Reload(Box<Config>),
+    State,
+    ReleaseAll,
+}
+
+#[cfg(unix)]
+pub use server::Control;
+
+// Off Unix there are no Unix sockets, so a control socket can never be started
+#[cfg(not(unix))]
+pub enum Control {}
+
+#[cfg(not(unix))]
+impl Control {
+    pub fn start(_path: &std::path::Path, _config: Option<std::path::PathBuf>) -> std::io::Result<Control> {
+        Err(std::io::Error::other("control sockets need a Unix system"))
+    }
+
+    pub fn pending(&self) -> Option<Request> {
+        match *self {}
+    }
+}
+
+// A command waiting for dispatch to carry it out
+pub struct Request {
+    pub command: Command,
+    pub reply: Reply,
+}
+
+// Where the client waiting on a Request is answered
+pub struct Reply(mpsc::Sender<Result<String, String>>);
+
+impl Reply {
+    // Ok with any output, each line of it ending in a newline, or Err with
+    // what went wrong
+    pub fn send(self, result: Result<String, String>) {
+        // the client may have hung up, there's no one left to tell
+        let _ = self.0.send(result);
+    }
+}
+
+#[cfg(unix)]
+mod server {
+    use std::fs::{self, DirBuilder, Permissions};
+    use std::io::{self, BufRead, BufReader, Write};
+    use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::{Path, PathBuf};
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    use log::{debug, info, warn};
+
+    use super::{Command, Reply, Request};
+    use crate::config;
+
+    // Longest a client waits for dispatch, which checks for commands at
+    // least once every read timeout
+    const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+    // Only the user running the agent can connect
+    const SOCKET_MODE: u32 = 0o600;
+    const PRIVATE_DIR_MODE: u32 = 0o700;
+
+    // Accepts on its own thread and reads each client on another, so a
+    // client that never sends anything holds up no one
+    pub struct Control {
+        path: PathBuf,
+        rx: mpsc::Receiver<Request>,
+    }
+
+    impl Control {
+        // `config` is the file reload loads, None if there isn't one. A
+        // socket left behind by an earlier run is replaced, anything else
+        // at path is left alone.
+        pub fn start(path: &Path, config: Option<PathBuf>) -> io::Result<Control> {
+            match fs::symlink_metadata(path) {
+                Ok(meta) if meta.file_type().is_socket() => fs::remove_file(path)?,
+                Ok(_) => return Err(io::Error::new(io::ErrorKind::AlreadyExists, "something other than a socket is already there")),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {},
+                Err(e) => return Err(e),
+            }
+
+            let listener = bind_private(path)?;
+            info!("Listening for commands on {:?}", path);
+
+            let (tx, rx) = mpsc::channel();
+            thread::Builder::new().name("control".to_string()).spawn(move || {
+                for stream in listener.incoming() {
+                    let stream = match stream {
+                        Ok(stream) => stream,
+                        Err(e) => {
+                            warn!("Could not accept a control connection: {}", e);
+                            continue;
+                        },
+                    };
+
+                    let tx = tx.clone();
+                    let config = config.clone();
+                    let spawned = thread::Builder::new().name("control-client".to_string()).spawn(move || {
+                        if let Err(e) = serve(stream, &tx, config.as_deref()) { debug!("Control connection closed: {}", e); }
+                    });
+                    if let Err(e) = spawned { warn!("Could not serve a control connection: {}", e); }
+                }
+            })?;
+
+            Ok(Control { path: path.to_path_buf(), rx })
+        }
+
+        // The oldest command dispatch hasn't carried out yet, if there is one
+        pub fn pending(&self) -> Option<Request> {
+            self.rx.try_recv().ok()
+        }
+    }
+
+    impl Drop for Control {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+
+    // Bound inside a directory only this user can get into and moved to path
+    // once it's SOCKET_MODE, as binding at path straight away would leave it
+    // open to everyone until the chmod, for as long as the umask allows
+    fn bind_private(path: &Path) -> io::Result<UnixListener> {
+        let name = path.file_name().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "the socket path has no file name"))?;
+        let mut dir_name = std::ffi::OsString::from(".");
+        dir_name.push(name);
+        dir_name.push(format!(".{}", std::process::id()));
+        let dir = path.with_file_name(dir_name);
+
+        DirBuilder::new().mode(PRIVATE_DIR_MODE).create(&dir)?;
+        let bound = dir.join("socket");
+        let result = UnixListener::bind(&bound).and_then(|listener| {
+            fs::set_permissions(&bound, Permissions::from_mode(SOCKET_MODE))?;
+            fs::rename(&bound, path)?;
+            Ok(listener)
+        });
+
+        let _ = fs::remove_file(&bound);
+        let _ = fs::remove_dir(&dir);
+        result
+    }
+
+    // Until the client hangs up or dispatch has shut down
+    fn serve(stream: UnixStream, tx: &mpsc::Sender<Request>, config: Option<&Path>) -> io::Result<()> {
+        let mut writer = stream.try_clone()?;
+
+        for line in BufReader::new(stream).lines() {
+            let line = line?;
+            let result = match parse(line.trim(), config) {
+                Ok(command) => send(command, tx),
+                Err(message) => Err(message),
+            };
+
+            match result {
+                Ok(output) => {
+                    write!(writer, "{}", output)?;
+                    writeln!(writer, "ok")?;
+                },
+                Err(message) => writeln!(writer, "error: {}", message)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn parse(line: &str, config: Option<&Path>) -> Result<Command, String> {
+        let (name, arg) = match line.split_once(' ') {
+            Some((name, arg)) => (name, arg.trim()),
+            None => (line, ""),
+        };
+
+        match (name, arg) {
+            ("profile", "") => Err("profile needs a name or number".to_string()),
+            ("profile", profile) => Ok(Command::Profile(profile.to_string())),
+            ("reload", "") => {
+                let path = config.ok_or("there's no --config to reload")?;
+                match config::load(path) {
+                    Ok(config) => {
+                        info!("Reloaded {:?}", path);
+                        Ok(Command::Reload(Box::new(config)))
+                    },
+                    Err(e) => Err(format!("could not reload {:?}, keeping the old config: {}", path, e)),
+                }
+            },
+            ("state", "") => Ok(Command::State),
+            ("release-all", "") => Ok(Command::ReleaseAll),
+            ("reload", _) | ("state", _) | ("release-all", _) => Err(format!("{} doesn't take anything after it", name)),
+            _ => Err(format!("unknown command {:?}, the commands are profile, reload, state and release-all", line)),
+        }
+    }
+
+    fn send(command: Command, tx: &mpsc::Sender<Request>) -> Result<String, String> {
+        let (reply, replied) = mpsc::channel();
+        tx.send(Request { command, reply: Reply(reply) }).map_err(|_| "shutting down".to_string())?;
+
+        replied.recv_timeout(REPLY_TIMEOUT).unwrap_or_else(|_| Err("no reply, the agent may be stuck".to_string()))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn socket_is_private_from_the_start() {
+            let dir = std::env::temp_dir().join(format!("mac-usb-controller-control-{}", std::process::id()));
+            fs::create_dir_all(&dir).unwrap();
+            let path = dir.join("control.sock");
+
+            let control = Control::start(&path, None).unwrap();
+            let meta = fs::symlink_metadata(&path).unwrap();
+            assert!(meta.file_type().is_socket());
+            assert_eq!(meta.permissions().mode() & 0o777, SOCKET_MODE);
+            // nothing left over from binding it
+            assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+            let mut stream = UnixStream::connect(&path).unwrap();
+            writeln!(stream, "bogus").unwrap();
+            let mut line = String::new();
+            BufReader::new(stream).read_line(&mut line).unwrap();
+            assert!(line.starts_with("error: unknown command"), "{}", line);
+
+            drop(control);
+            assert!(!path.exists());
+            fs::remove_dir(&dir).unwrap();
+        }
+    }
+}
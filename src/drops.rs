@@ -0,0 +1,73 @@
+// Reports lost on the way from the pad, for --drops. Some pads count up in
+// the report's last byte, so a jump of more than one there is that many
+// reports missed. The known HORIPADs leave it at 0, which is said once
+// rather than logging a loss rate of nothing.
+
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+
+// Reports with the byte unchanged before it's taken not to be a counter
+const NOT_COUNTING: u32 = 100;
+
+pub struct Drops {
+    // how often a summary is logged
+    every: Duration,
+    since: Instant,
+    last: Option<u8>,
+    reports: u32,
+    missed: u32,
+    // reports in a row that didn't move the counter at all
+    unchanged: u32,
+    // set once it's clear there's no counter, nothing is logged after
+    given_up: bool,
+}
+
+impl Drops {
+    pub fn new(every: Duration) -> Drops {
+        Drops { every, since: Instant::now(), last: None, reports: 0, missed: 0, unchanged: 0, given_up: false }
+    }
+
+    // With the last byte of each report read from the pad. A repeat doesn't
+    // count as going backwards a full 255.
+    pub fn record(&mut self, counter: u8) {
+        if self.given_up { return; }
+
+        if let Some(last) = self.last {
+            match counter.wrapping_sub(last) {
+                0 => self.unchanged += 1,
+                step => {
+                    self.unchanged = 0;
+                    self.missed += step as u32 - 1;
+                },
+            }
+        }
+        self.last = Some(counter);
+        self.reports += 1;
+
+        if self.unchanged >= NOT_COUNTING {
+            warn!("This pad doesn't count its reports in the last byte, so --drops can't tell if any are lost");
+            self.given_up = true;
+        }
+    }
+
+    // Logs a summary and starts over once every period. A gap in the
+    // counter across a period's edge counts in the new one.
+    pub fn report(&mut self) {
+        if self.given_up || self.since.elapsed() < self.every { return; }
+
+        if self.reports > 0 {
+            let sent = self.reports + self.missed;
+            info!("Dropped {} of {} reports ({:.2}%)", self.missed, sent, self.missed as f64 * 100.0 / sent as f64);
+        }
+
+        self.since = Instant::now();
+        self.reports = 0;
+        self.missed = 0;
+    }
+
+    // A reconnected pad starts counting from wherever it likes
+    pub fn reset(&mut self) {
+        self.last = None;
+    }
+}
@@ -0,0 +1,75 @@
+use std::fmt;
+use std::io;
+
+use crate::config::ConfigError;
+use crate::{DeviceMatch, ParseError};
+
+#[derive(Debug)]
+pub enum Error {
+    Usage(String),
+    Config(ConfigError),
+    Io { context: String, error: io::Error },
+    DeviceNotFound(DeviceMatch),
+    Hid(hidapi::HidError),
+    // a report handed to Controller::process that isn't one
+    Report(ParseError),
+    // the tfc context couldn't be made, so nothing could ever be sent
+    Backend(tfc::Error),
+    Output(tfc::Error),
+    Calibration(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Usage(message) => write!(f, "{}", message),
+            Error::Config(e) => write!(f, "{}", e),
+            Error::Io { context, error } => write!(f, "{}: {}", context, error),
+            Error::DeviceNotFound(target) => write!(f, "Unable to find provided target {}", target),
+            Error::Hid(e) => write!(f, "{}", e),
+            Error::Report(e) => write!(f, "Invalid report: {}", e),
+            Error::Backend(e) => write!(f, "Could not start sending key events: {}. On macOS this needs Accessibility \
+                permission for this program (or the terminal running it), under System Settings > Privacy & Security > \
+                Accessibility", e),
+            Error::Output(e) => write!(f, "Could not send input: {}", e),
+            Error::Calibration(message) => write!(f, "Calibration failed: {}", message),
+        }
+    }
+}
+
+impl Error {
+    // Exit codes are listed in the README and --help, keep them stable
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Io { .. } => 1,
+            Error::Usage(_) => 2,
+            Error::Config(_) => 3,
+            Error::DeviceNotFound(_) => 4,
+            Error::Hid(_) | Error::Report(_) => 5,
+            Error::Output(_) => 6,
+            Error::Calibration(_) => 7,
+            Error::Backend(_) => 8,
+        }
+    }
+}
+
+// hidapi's error isn't a std::error::Error, so there's no source() to give
+impl std::error::Error for Error {}
+
+impl From<ConfigError> for Error {
+    fn from(e: ConfigError) -> Error {
+        Error::Config(e)
+    }
+}
+
+impl From<hidapi::HidError> for Error {
+    fn from(e: hidapi::HidError) -> Error {
+        Error::Hid(e)
+    }
+}
+
+impl From<tfc::Error> for Error {
+    fn from(e: tfc::Error) -> Error {
+        Error::Output(e)
+    }
+}
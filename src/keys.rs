@@ -0,0 +1,112 @@
+// Key names as they're written in a config. tfc's own identifier names
+// ("P", "Space", "Escape", "UpArrow"...) are the canonical spelling, any
+// case goes, and ALIASES adds the other names people tend to write.
+
+use std::fmt;
+
+use tfc::{Enum, Key};
+
+// Left to right, the name someone might write and the key they'd mean
+const ALIASES: [(&str, Key); 39] = [
+    ("ArrowUp", Key::UpArrow),
+    ("ArrowDown", Key::DownArrow),
+    ("ArrowLeft", Key::LeftArrow),
+    ("ArrowRight", Key::RightArrow),
+    ("Up", Key::UpArrow),
+    ("Down", Key::DownArrow),
+    ("Left", Key::LeftArrow),
+    ("Right", Key::RightArrow),
+    ("Enter", Key::ReturnOrEnter),
+    ("Return", Key::ReturnOrEnter),
+    ("Backspace", Key::DeleteOrBackspace),
+    ("Delete", Key::ForwardDelete),
+    ("Del", Key::ForwardDelete),
+    ("Esc", Key::Escape),
+    ("Ctrl", Key::Control),
+    ("Option", Key::Alt),
+    ("Cmd", Key::Meta),
+    ("Command", Key::Meta),
+    ("Super", Key::Meta),
+    ("RightCtrl", Key::RightControl),
+    ("RightOption", Key::RightAlt),
+    ("RightCmd", Key::RightMeta),
+    ("PgUp", Key::PageUp),
+    ("PgDn", Key::PageDown),
+    ("Backtick", Key::Grave),
+    ("Apostrophe", Key::Quote),
+    ("Equals", Key::Equal),
+    ("Dot", Key::Period),
+    ("Hyphen", Key::Minus),
+    ("0", Key::N0),
+    ("1", Key::N1),
+    ("2", Key::N2),
+    ("3", Key::N3),
+    ("4", Key::N4),
+    ("5", Key::N5),
+    ("6", Key::N6),
+    ("7", Key::N7),
+    ("8", Key::N8),
+    ("9", Key::N9),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseKeyError {
+    pub name: String,
+}
+
+impl fmt::Display for ParseKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown key {:?}", self.name)
+    }
+}
+
+impl std::error::Error for ParseKeyError {}
+
+pub fn parse_key(name: &str) -> Result<Key, ParseKeyError> {
+    Key::iter()
+        .find(|key| key.identifier_name().eq_ignore_ascii_case(name))
+        .or_else(|| ALIASES.iter().find(|(alias, _)| alias.eq_ignore_ascii_case(name)).map(|&(_, key)| key))
+        .ok_or_else(|| ParseKeyError { name: name.to_string() })
+}
+
+// The canonical name, which parse_key always reads back as the same key
+pub fn key_name(key: Key) -> &'static str {
+    key.identifier_name()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_key_name_reads_back() {
+        for key in Key::iter() {
+            assert_eq!(parse_key(key_name(key)), Ok(key), "{}", key_name(key));
+        }
+    }
+
+    #[test]
+    fn any_case_goes() {
+        for key in Key::iter() {
+            let name = key_name(key);
+            assert_eq!(parse_key(&name.to_ascii_lowercase()), Ok(key), "{}", name);
+            assert_eq!(parse_key(&name.to_ascii_uppercase()), Ok(key), "{}", name);
+        }
+        assert_eq!(parse_key("pgup"), Ok(Key::PageUp));
+        assert_eq!(parse_key("ESC"), Ok(Key::Escape));
+    }
+
+    #[test]
+    fn every_alias_is_its_key() {
+        for &(alias, key) in ALIASES.iter() {
+            assert_eq!(parse_key(alias), Ok(key), "{}", alias);
+        }
+    }
+
+    #[test]
+    fn unknown_names_are_errors() {
+        assert_eq!(parse_key("Hyper"), Err(ParseKeyError { name: "Hyper".to_string() }));
+        assert_eq!(parse_key(""), Err(ParseKeyError { name: String::new() }));
+        assert_eq!(parse_key("Hyper").unwrap_err().to_string(), "unknown key \"Hyper\"");
+    }
+}
@@ -0,0 +1,77 @@
+// Time from a report being read to the last event it caused going out, for
+// --latency. Samples go in fixed size buckets so a long session uses no
+// more memory than a short one.
+
+use std::time::{Duration, Instant};
+
+use log::info;
+
+use crate::millis;
+
+// 10us per bucket up to 100ms, anything slower lands in the last one
+const BUCKET_US: u64 = 10;
+const BUCKETS: usize = 10_000;
+
+pub struct Latency {
+    // how often a summary is logged
+    every: Duration,
+    since: Instant,
+    buckets: Vec<u32>,
+    count: u32,
+    total: Duration,
+    min: Duration,
+    max: Duration,
+}
+
+impl Latency {
+    pub fn new(every: Duration) -> Latency {
+        Latency {
+            every,
+            since: Instant::now(),
+            buckets: vec![0; BUCKETS],
+            count: 0,
+            total: Duration::ZERO,
+            min: Duration::MAX,
+            max: Duration::ZERO,
+        }
+    }
+
+    pub fn record(&mut self, latency: Duration) {
+        let bucket = (latency.as_micros() as u64 / BUCKET_US).min(BUCKETS as u64 - 1);
+        self.buckets[bucket as usize] += 1;
+
+        self.count += 1;
+        self.total += latency;
+        self.min = self.min.min(latency);
+        self.max = self.max.max(latency);
+    }
+
+    // Logs a summary and starts over once every period, quiet periods
+    // with nothing sent aren't logged
+    pub fn report(&mut self) {
+        if self.since.elapsed() < self.every { return; }
+
+        if self.count > 0 {
+            info!("Latency over {} reports: min {:.3}ms, avg {:.3}ms, max {:.3}ms, p99 {:.3}ms",
+                self.count, millis(self.min), millis(self.total / self.count), millis(self.max), millis(self.percentile(0.99)));
+        }
+
+        *self = Latency::new(self.every);
+    }
+
+    // Upper edge of the bucket the percentile falls in, never more than
+    // the slowest sample
+    fn percentile(&self, p: f64) -> Duration {
+        let wanted = (self.count as f64 * p).ceil() as u32;
+        let mut seen = 0;
+
+        for (i, &n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if seen >= wanted {
+                return Duration::from_micros((i as u64 + 1) * BUCKET_US).min(self.max);
+            }
+        }
+
+        self.max
+    }
+}
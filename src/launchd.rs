@@ -0,0 +1,119 @@
+// --install and --uninstall, a launchd agent that starts the controller at
+// login with the flags --install was given. launchd runs it from / with no
+// terminal, so paths are made absolute and output goes to a log file.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use log::info;
+
+use mac_usb_controller::error::Error;
+
+const LABEL: &str = "com.tophelders.mac-usb-controller";
+
+// Flags whose value is a path, relative to where --install was run
+const PATH_FLAGS: [&str; 5] = ["--config", "--record", "--replay", "--log-file", "--control"];
+
+pub fn install(flags: &[String]) -> Result<(), Error> {
+    let path = plist_path()?;
+    let binary = env::current_exe()
+        .and_then(fs::canonicalize)
+        .map_err(|error| Error::Io { context: "Could not find this program's path".to_string(), error })?;
+    let dir = env::current_dir()
+        .map_err(|error| Error::Io { context: "Could not read the current directory".to_string(), error })?;
+    let log = home()?.join("Library/Logs/mac-usb-controller.log");
+
+    let mut arguments = vec![binary.display().to_string()];
+    let mut flags = flags.iter();
+    while let Some(flag) = flags.next() {
+        arguments.push(flag.clone());
+        if PATH_FLAGS.contains(&flag.as_str()) {
+            if let Some(value) = flags.next() { arguments.push(dir.join(value).display().to_string()); }
+        }
+    }
+
+    let plist = render(&arguments, &log);
+    let existing = fs::read_to_string(&path).ok();
+    if existing.as_deref() == Some(plist.as_str()) {
+        println!("{} is already installed with these flags", path.display());
+        return Ok(());
+    }
+
+    let io_error = |error| Error::Io { context: format!("Could not write {:?}", path), error };
+    fs::create_dir_all(path.parent().unwrap_or(Path::new("/"))).map_err(io_error)?;
+    fs::write(&path, plist).map_err(io_error)?;
+    info!("Wrote {}", path.display());
+
+    // the old agent keeps running its old flags until it's unloaded
+    if existing.is_some() { println!("launchctl unload {}", quote(&path)); }
+    println!("launchctl load {}", quote(&path));
+    println!("The log goes to {}", log.display());
+    Ok(())
+}
+
+pub fn uninstall() -> Result<(), Error> {
+    let path = plist_path()?;
+
+    match fs::remove_file(&path) {
+        Ok(()) => {
+            info!("Removed {}", path.display());
+            println!("launchctl remove {}", LABEL);
+            Ok(())
+        },
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            println!("Nothing to uninstall, {} doesn't exist", path.display());
+            Ok(())
+        },
+        Err(error) => Err(Error::Io { context: format!("Could not remove {:?}", path), error }),
+    }
+}
+
+fn home() -> Result<PathBuf, Error> {
+    env::var_os("HOME").map(PathBuf::from).ok_or_else(|| Error::Usage("HOME isn't set, so there's no LaunchAgents folder to use".to_string()))
+}
+
+fn plist_path() -> Result<PathBuf, Error> {
+    Ok(home()?.join("Library/LaunchAgents").join(format!("{}.plist", LABEL)))
+}
+
+// Started at login and again if it crashes, but left stopped after a clean
+// exit such as holding Home to quit
+fn render(arguments: &[String], log: &Path) -> String {
+    let arguments: String = arguments.iter().map(|arg| format!("        <string>{}</string>\n", escape(arg))).collect();
+    let log = escape(&log.display().to_string());
+
+    format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">
+<plist version=\"1.0\">
+<dict>
+    <key>Label</key>
+    <string>{}</string>
+    <key>ProgramArguments</key>
+    <array>
+{}    </array>
+    <key>RunAtLoad</key>
+    <true/>
+    <key>KeepAlive</key>
+    <dict>
+        <key>SuccessfulExit</key>
+        <false/>
+    </dict>
+    <key>StandardOutPath</key>
+    <string>{}</string>
+    <key>StandardErrorPath</key>
+    <string>{}</string>
+</dict>
+</plist>
+", LABEL, arguments, log, log)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+// For pasting into a shell
+fn quote(path: &Path) -> String {
+    format!("'{}'", path.display().to_string().replace('\'', "'\\''"))
+}
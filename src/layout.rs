@@ -0,0 +1,169 @@
+// How a pad's raw reports are read into an Input. Everything past decoding
+// only sees Input, so another controller just needs a decoder of its own
+// and a name for --layout to pick it by.
+
+use crate::{Input, ParseError, REPORT_LEN};
+
+// Analog mode HORIPAD reports append ZL then ZR pressure to the usual report
+const TRIGGER_BYTES: usize = 2;
+
+pub trait ReportDecoder {
+    // Lengths of the reports the pad sends, not counting any report id
+    fn lengths(&self) -> &'static [usize];
+    // One report of one of those lengths, with any report id taken off
+    fn decode(&self, report: &[u8]) -> Input;
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Layout {
+    Horipad,
+    Generic,
+}
+
+impl Layout {
+    pub fn decoder(self) -> &'static dyn ReportDecoder {
+        match self {
+            Layout::Horipad => &Horipad,
+            Layout::Generic => &Generic,
+        }
+    }
+}
+
+// [buttons, extra, dpad, lx, ly, rx, ry, unused], with the triggers after
+// that in analog mode. The report's length tells the two modes apart.
+pub struct Horipad;
+
+impl ReportDecoder for Horipad {
+    fn lengths(&self) -> &'static [usize] {
+        &[REPORT_LEN, REPORT_LEN + TRIGGER_BYTES]
+    }
+
+    fn decode(&self, report: &[u8]) -> Input {
+        let mut data = [0u8; REPORT_LEN];
+        data.copy_from_slice(&report[..REPORT_LEN]);
+
+        let mut input = Input::new(data);
+        if report.len() > REPORT_LEN { input.triggers = Some((report[REPORT_LEN], report[REPORT_LEN + 1])); }
+
+        input
+    }
+}
+
+// Sticks first, [lx, ly, rx, ry, hat, buttons 1-8, buttons 9-13], as a lot
+// of cheap pads without a layout of their own send it. The buttons go in
+// the HORIPAD's order, so button 1 is Y and 13 is Home, and the hat counts
+// 0-7 clockwise from up with anything else centered, the same as its dpad.
+pub struct Generic;
+
+impl ReportDecoder for Generic {
+    fn lengths(&self) -> &'static [usize] {
+        &[7]
+    }
+
+    fn decode(&self, report: &[u8]) -> Input {
+        Input {
+            buttons: report[5],
+            extra: report[6],
+            dpad: report[4],
+            lstick1: report[0],
+            lstick2: report[1],
+            rstick1: report[2],
+            rstick2: report[3],
+            ..Input::default()
+        }
+    }
+}
+
+// With a report id the first byte has to match it and the report follows.
+// Without one it's told by length alone: a report a byte longer than one of
+// the layout's lengths is always taken as an id in front of a report,
+// whatever the id is, even if that's also a length of its own. The known
+// pads don't send one, and none has lengths a byte apart.
+pub fn decode(decoder: &dyn ReportDecoder, data: &[u8], report_id: Option<u8>) -> Result<Input, ParseError> {
+    let lengths = decoder.lengths();
+    let prefixed = lengths.iter().any(|&len| data.len() == len + 1);
+    let offset = if report_id.is_some() || prefixed { 1 } else { 0 };
+    let len = data.len().saturating_sub(offset);
+
+    if !lengths.contains(&len) {
+        return Err(ParseError::Length { expected: lengths.iter().map(|len| offset + len).collect(), got: data.len() });
+    }
+
+    if let Some(id) = report_id {
+        if data[0] != id { return Err(ParseError::ReportId { expected: id, got: data[0] }); }
+    }
+
+    Ok(decoder.decode(&data[offset..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REPORT: [u8; REPORT_LEN] = [0x01, 0x02, 0x00, 0x10, 0x20, 0x30, 0x40, 0x00];
+
+    fn with_id(id: u8, report: &[u8]) -> Vec<u8> {
+        let mut data = vec![id];
+        data.extend_from_slice(report);
+        data
+    }
+
+    fn analog() -> Vec<u8> {
+        let mut data = REPORT.to_vec();
+        data.extend_from_slice(&[0xaa, 0xbb]);
+        data
+    }
+
+    fn assert_report(input: Input, triggers: Option<(u8, u8)>) {
+        assert_eq!((input.buttons, input.extra, input.dpad), (0x01, 0x02, 0x00));
+        assert_eq!((input.lstick1, input.lstick2, input.rstick1, input.rstick2), (0x10, 0x20, 0x30, 0x40));
+        assert_eq!(input.triggers, triggers);
+    }
+
+    #[test]
+    fn horipad_reports_without_an_id() {
+        assert_report(decode(&Horipad, &REPORT, None).unwrap(), None);
+        assert_report(decode(&Horipad, &analog(), None).unwrap(), Some((0xaa, 0xbb)));
+    }
+
+    #[test]
+    fn horipad_reports_a_byte_long_are_prefixed() {
+        // whatever the first byte is, it's taken for an id
+        assert_report(decode(&Horipad, &with_id(0x3f, &REPORT), None).unwrap(), None);
+        assert_report(decode(&Horipad, &with_id(0x00, &analog()), None).unwrap(), Some((0xaa, 0xbb)));
+    }
+
+    #[test]
+    fn horipad_reports_with_an_id() {
+        assert_report(decode(&Horipad, &with_id(0x01, &REPORT), Some(0x01)).unwrap(), None);
+        assert_report(decode(&Horipad, &with_id(0x01, &analog()), Some(0x01)).unwrap(), Some((0xaa, 0xbb)));
+
+        // with an id expected, 8 and 10 bytes are a byte short
+        for data in [REPORT.to_vec(), analog()].iter() {
+            match decode(&Horipad, data, Some(0x01)) {
+                Err(ParseError::Length { expected, got }) => assert_eq!((expected, got), (vec![9, 11], data.len())),
+                other => panic!("{} bytes gave {:?}", data.len(), other),
+            }
+        }
+    }
+
+    #[test]
+    fn horipad_wrong_report_id() {
+        for report in [REPORT.to_vec(), analog()].iter() {
+            match decode(&Horipad, &with_id(0x02, report), Some(0x01)) {
+                Err(ParseError::ReportId { expected, got }) => assert_eq!((expected, got), (0x01, 0x02)),
+                other => panic!("{:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn horipad_other_lengths() {
+        for &len in &[0, 1, 7, 12, 64] {
+            match decode(&Horipad, &vec![0; len], None) {
+                Err(ParseError::Length { got, .. }) => assert_eq!(got, len),
+                other => panic!("{} bytes gave {:?}", len, other),
+            }
+        }
+    }
+}
@@ -0,0 +1,3201 @@
+// Reads a Hori switch controller and turns it into key and mouse events.
+// Controller does the mapping and can be fed reports directly, run reads
+// the devices and drives one Controller per pad until stop is called.
+
+pub mod apps;
+mod channel;
+pub mod config;
+pub mod control;
+mod drops;
+pub mod error;
+pub mod keys;
+pub mod layout;
+mod latency;
+pub mod overlay;
+pub mod pad;
+mod permissions;
+pub mod record;
+pub mod sink;
+#[cfg(target_os = "linux")]
+pub mod uinput;
+pub mod watch;
+
+use hidapi;
+use std::collections::hash_map::RandomState;
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{debug, error, info, warn};
+use tfc::{Enum, Key, MouseButton};
+
+use apps::AppWatcher;
+use control::{Command, Control, Request};
+use config::{Action, Button, ButtonTable, Calibration, Config, DpadMode, Mapping, MouseCurve, Profile, Sequence, Socd, StickChord, StickMode, Trigger, BUTTONS, BUTTON_COUNT};
+use drops::Drops;
+use error::Error;
+use latency::Latency;
+use layout::Layout;
+use overlay::{Overlay, Snapshot};
+use pad::{PadSink, PadState};
+use record::{Recorder, Replay};
+use sink::{OutputSink, TimedSink};
+use watch::ConfigWatcher;
+
+#[derive(Copy, Clone)]
+#[repr(u8)]
+pub enum Buttons {
+    Y = 0x01,
+    B = 0x02,
+    A = 0x04,
+    X = 0x08,
+    L = 0x10,
+    R = 0x20,
+    ZL = 0x40,
+    ZR = 0x80,
+}
+
+#[derive(Copy, Clone)]
+#[repr(u8)]
+pub enum Extra {
+    Minus = 0x01,
+    Plus = 0x02,
+    LSB = 0x04,
+    RSB = 0x08,
+    Home = 0x10,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[repr(u8)]
+pub enum Dpad {
+    Off = 0x00,
+    U = 0x01,
+    R = 0x02,
+    D = 0x04,
+    L = 0x08,
+    UR = 0x03,
+    DR = 0x06,
+    UL = 0x09,
+    DL = 0x0C,
+}
+
+// Bound diagonals are tracked above the Dpad bits, so State::dpad holds
+// either kind
+const DIAGONAL_BITS: [(Dpad, u8, Button); 4] = [
+    (Dpad::UL, 0x10, Button::UpLeft),
+    (Dpad::UR, 0x20, Button::UpRight),
+    (Dpad::DL, 0x40, Button::DownLeft),
+    (Dpad::DR, 0x80, Button::DownRight),
+];
+
+// Every bit _handle_dpad can send, in the order they're handled
+const DPAD_BUTTONS: [(u8, Button); 8] = [
+    (Dpad::U as u8, Button::Up),
+    (Dpad::D as u8, Button::Down),
+    (Dpad::L as u8, Button::Left),
+    (Dpad::R as u8, Button::Right),
+    (0x10, Button::UpLeft),
+    (0x20, Button::UpRight),
+    (0x40, Button::DownLeft),
+    (0x80, Button::DownRight),
+];
+
+// Steepness of MouseCurve::Exponential, higher keeps more of the stick's
+// travel slow
+const MOUSE_CURVE_EXPONENT: f32 = 3.0;
+
+// How far (out of 128) an axis has to be pushed for the stick to count
+// as pressing a direction key in WASD mode, or as pushed for a stick chord
+const WASD_THRESHOLD: i32 = 64;
+
+// Polls between scroll steps while a stick is scrolling, since scrolling
+// every ~1ms poll is far too fast to follow
+const SCROLL_INTERVAL: u8 = 30;
+
+// The same time between steps, for taking the poll's place with mouse_rate
+const SCROLL_STEP: Duration = Duration::from_millis(30);
+
+// Keys then mouse buttons, for counting how many buttons hold each action
+const ACTION_COUNT: usize = Key::COUNT as usize + MouseButton::COUNT as usize;
+
+// Cleared by the SIGINT/SIGTERM handler, checked once per poll
+static RUNNING: AtomicBool = AtomicBool::new(true);
+
+// Up to how much longer than the backoff a reconnect waits, as a fraction
+// of it, so instances restarted together don't all retry at once
+const RECONNECT_JITTER: f64 = 0.1;
+
+// Times in a row a lost device can be seen but not opened before the
+// reader gives up, as something like a missing permission won't fix itself
+const MAX_OPEN_FAILURES: u32 = 5;
+
+// HID usages of the interface a gamepad's reports come from
+const USAGE_PAGE_GENERIC_DESKTOP: u16 = 0x01;
+const USAGE_JOYSTICK: u16 = 0x04;
+const USAGE_GAMEPAD: u16 = 0x05;
+
+// Longest the reader sleeps waiting to reconnect before checking it
+// should still be running
+const SHUTDOWN_CHECK: Duration = Duration::from_millis(100);
+
+// How long --calibrate samples the sticks for, and how many reports at
+// rest it needs in that time to trust the result
+const CALIBRATION_TIME: Duration = Duration::from_secs(2);
+const MIN_CALIBRATION_SAMPLES: u32 = 50;
+
+// How long --rebind waits for a button before taking it that there are
+// no more to bind
+const REBIND_TIMEOUT: Duration = Duration::from_secs(20);
+
+// How long --test shows the input for, unless it's stopped sooner
+const TEST_TIME: Duration = Duration::from_secs(30);
+
+// Most presses kept for matching sequences against, however long their
+// windows are
+const SEQUENCE_LOG_LEN: usize = 32;
+
+// Reports the reader can get ahead of dispatch before the oldest are dropped
+const QUEUE_SIZE: usize = 32;
+
+// Where reports come from
+pub enum Source {
+    Device { target: DeviceMatch, recorder: Option<Recorder> },
+    Replay(Replay),
+}
+
+// What the reader thread hands to dispatch, along with which device it's from
+#[derive(Debug)]
+enum Report {
+    // the device was opened, with the id it's known by for the session
+    Connected(String),
+    Input(Input),
+    // the read failed, assume nothing is held
+    NoInput,
+    // gave up on the device, everything should be let go until it's back
+    Disconnected,
+}
+
+// What a full queue can lose. A later report takes the place of an input
+// or a failed read, but a lost Connected or Disconnected would leave
+// dispatch with the wrong profile or a lost pad's keys held.
+fn droppable((_, report): &(usize, Report)) -> bool {
+    matches!(report, Report::Input(_) | Report::NoInput)
+}
+
+// How often the device is read and how often held state is resynced
+#[derive(Debug, Clone)]
+pub struct PollOptions {
+    // sleep after each read, zero to read as fast as the device reports
+    pub interval: Duration,
+    // polls between clear_state calls, zero to never clear
+    pub clear_every: u32,
+    // longest a read waits for a report before the last input is reused
+    pub read_timeout: Duration,
+    // for pads that put a report id byte in front of the report
+    pub report_id: Option<u8>,
+    // how the pad's reports are laid out
+    pub layout: Layout,
+    // which of a matching device's HID interfaces to read
+    pub interface: InterfaceMatch,
+    // how often to log a latency summary, None to not measure it
+    pub latency: Option<Duration>,
+    // how often to log how many reports went missing, None to not count them
+    pub drops: Option<Duration>,
+    // consecutive failed reads before a device is treated as unplugged,
+    // fewer are taken as nothing being held
+    pub max_read_errors: u32,
+    // wait before the first attempt to reopen an unplugged device, doubled
+    // after each failed one up to reconnect_max
+    pub reconnect_min: Duration,
+    pub reconnect_max: Duration,
+    // how long a device can go without a report before it's taken as asleep
+    // and reopened, None to wait forever. Only for pads that keep reporting
+    // while nothing changes.
+    pub watchdog: Option<Duration>,
+    // shut down once this many reports have been handled, None to keep going
+    pub max_reports: Option<u64>,
+}
+
+impl Default for PollOptions {
+    fn default() -> PollOptions {
+        PollOptions {
+            interval: Duration::from_millis(1),
+            clear_every: 7,
+            read_timeout: Duration::from_millis(100),
+            report_id: None,
+            layout: Layout::Horipad,
+            interface: InterfaceMatch::default(),
+            latency: None,
+            drops: None,
+            max_read_errors: 100,
+            reconnect_min: Duration::from_millis(100),
+            reconnect_max: Duration::from_secs(5),
+            watchdog: None,
+            max_reports: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum DeviceMatch {
+    ByName(String),
+    ByVidPid(u16, u16),
+    BySerial(String),
+}
+
+impl fmt::Display for DeviceMatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceMatch::ByName(name) => write!(f, "{:?}", name),
+            DeviceMatch::ByVidPid(vid, pid) => write!(f, "{:04x}:{:04x}", vid, pid),
+            DeviceMatch::BySerial(serial) => write!(f, "with serial {:?}", serial),
+        }
+    }
+}
+
+// Narrows a DeviceMatch down to some of its HID interfaces, for pads with
+// more than one. Anything left as None matches every interface.
+#[derive(Debug, Default, Clone)]
+pub struct InterfaceMatch {
+    pub usage_page: Option<u16>,
+    pub usage: Option<u16>,
+    pub number: Option<i32>,
+}
+
+impl InterfaceMatch {
+    fn matches(&self, device_info: &hidapi::HidDeviceInfo) -> bool {
+        self.usage_page.is_none_or(|page| device_info.usage_page == page)
+            && self.usage.is_none_or(|usage| device_info.usage == usage)
+            && self.number.is_none_or(|number| device_info.interface_number == number)
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Input {
+    pub buttons: u8,
+    pub extra: u8,
+    pub dpad: u8,
+    pub lstick1: u8,
+    pub lstick2: u8,
+    pub rstick1: u8,
+    pub rstick2: u8,
+    pub unused: u8,
+    // ZL and ZR pressure 0-255, only sent in analog mode
+    pub triggers: Option<(u8, u8)>,
+    // when the report was read, for the latency in the event log
+    pub read_at: Instant,
+}
+
+// Bytes in a HORIPAD report, not counting any report id or triggers
+pub const REPORT_LEN: usize = 8;
+
+// Longest report a full speed USB device can send
+const MAX_REPORT_LEN: usize = 64;
+
+#[derive(Debug)]
+pub enum ParseError {
+    // every length the layout accepts
+    Length { expected: Vec<usize>, got: usize },
+    ReportId { expected: u8, got: u8 },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Length { expected, got } => {
+                let expected: Vec<String> = expected.iter().map(|len| len.to_string()).collect();
+                write!(f, "expected a {} byte report, got {}", expected.join(" or "), got)
+            },
+            ParseError::ReportId { expected, got } => write!(f, "expected report id {:#04x}, got {:#04x}", expected, got),
+        }
+    }
+}
+
+impl Input {
+    // A HORIPAD report, see layout::decode for other pads
+    pub fn parse(data: &[u8], report_id: Option<u8>) -> Result<Input, ParseError> {
+        layout::decode(&layout::Horipad, data, report_id)
+    }
+
+    // The HORIPAD's byte order
+    pub fn new(data: [u8; REPORT_LEN]) -> Input {
+        Input {
+            buttons: data[0],
+            extra: data[1],
+            dpad: data[2],
+            lstick1: data[3],
+            lstick2: data[4],
+            rstick1: data[5],
+            rstick2: data[6],
+            unused: data[7],
+            triggers: None,
+            read_at: Instant::now(),
+        }
+    }
+
+    // Nothing held and both sticks resting where calibration says they do
+    pub fn at_rest(calibration: &Calibration) -> Input {
+        Input {
+            lstick1: calibration.lstick[0],
+            lstick2: calibration.lstick[1],
+            rstick1: calibration.rstick[0],
+            rstick2: calibration.rstick[1],
+            ..Input::default()
+        }
+    }
+
+    // Each axis relative to its calibrated center, -128 to 128 with 0 at
+    // rest. Positive is right and down, as the pad reports them.
+    pub fn lx(&self, calibration: &Calibration) -> i16 {
+        centered(self.lstick1, calibration.lstick[0])
+    }
+
+    pub fn ly(&self, calibration: &Calibration) -> i16 {
+        centered(self.lstick2, calibration.lstick[1])
+    }
+
+    pub fn rx(&self, calibration: &Calibration) -> i16 {
+        centered(self.rstick1, calibration.rstick[0])
+    }
+
+    pub fn ry(&self, calibration: &Calibration) -> i16 {
+        centered(self.rstick2, calibration.rstick[1])
+    }
+
+    // Held buttons, named as in the config. A diagonal is its two cardinals,
+    // the way the pad sends it.
+    pub fn pressed(&self) -> Vec<Button> {
+        let held = self.buttons as u16 | (self.extra as u16) << 8;
+        let dpad = convert_dpad(self.dpad) as u8;
+
+        BUTTONS.iter()
+            .copied()
+            .filter(|&button| match button {
+                Button::Up => dpad & Dpad::U as u8 != 0,
+                Button::Down => dpad & Dpad::D as u8 != 0,
+                Button::Left => dpad & Dpad::L as u8 != 0,
+                Button::Right => dpad & Dpad::R as u8 != 0,
+                _ => held & report_bit(button) != 0,
+            })
+            .collect()
+    }
+}
+
+// What's held and how far each axis is off center, e.g.
+// [A, X, Dpad:UR, LX:+40, ZL:200]. Triggers only show in analog mode, and
+// the last byte only when it isn't 0.
+impl fmt::Display for Input {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let held = self.buttons as u16 | (self.extra as u16) << 8;
+        let mut parts: Vec<String> = BUTTONS.iter()
+            .filter(|&&button| held & report_bit(button) != 0)
+            .map(|button| format!("{:?}", button))
+            .collect();
+
+        let dpad = convert_dpad(self.dpad);
+        if dpad != Dpad::Off { parts.push(format!("Dpad:{:?}", dpad)); }
+
+        let axes = [("LX", self.lstick1), ("LY", self.lstick2), ("RX", self.rstick1), ("RY", self.rstick2)];
+        for &(name, value) in &axes {
+            if value != 128 { parts.push(format!("{}:{:+}", name, value as i32 - 128)); }
+        }
+
+        if let Some((zl, zr)) = self.triggers {
+            if zl > 0 { parts.push(format!("ZL:{}", zl)); }
+            if zr > 0 { parts.push(format!("ZR:{}", zr)); }
+        }
+
+        // some variants send more buttons or a counter here
+        if self.unused != 0 { parts.push(format!("Unused:{:08b}", self.unused)); }
+
+        // padded as a whole, so --test's columns line up
+        f.pad(&format!("[{}]", parts.join(", ")))
+    }
+}
+
+fn centered(raw: u8, center: u8) -> i16 {
+    (raw as i16 - center as i16).clamp(-128, 128)
+}
+
+// Nothing held and both sticks at 128, the center of an uncalibrated pad
+impl Default for Input {
+    fn default() -> Input {
+        Input {
+            buttons: 0,
+            extra: 0,
+            dpad: 15,
+            lstick1: 128,
+            lstick2: 128,
+            rstick1: 128,
+            rstick2: 128,
+            unused: 0,
+            triggers: None,
+            read_at: Instant::now(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    // last physically observed button bits, diffed against each report
+    buttons: u8,
+    extra: u8,
+    dpad: u8,
+    // dpad bits as read, before SOCD cleaning
+    dpad_raw: u8,
+    // most recently pressed direction on each dpad axis, for Socd::LastInput
+    socd_last: u8,
+    // the rest are per stick, left then right, as they're used after swap_sticks
+    // position last seen, centered and after stick chords, for tick
+    sticks: [(i32, i32); 2],
+    // last directions emitted in WASD mode, in Dpad bits
+    stick_keys: [u8; 2],
+    // polls since the last scroll step
+    scroll_tick: [u8; 2],
+    // when the stick was pushed the way it's held now, and the sign of each
+    // axis since, for the scroll ramp
+    scroll_ramp: [Option<(Instant, (i32, i32))>; 2],
+    // bits of the report's last byte last sent, only those with a binding
+    unused: u8,
+    // physical Home state, kept apart from `extra` so clear_state can't hide a press
+    home: bool,
+    // when Home went down, while it's held and holding it can quit
+    home_since: Option<Instant>,
+    // buttons and extra bits (as in report_bit), and the raw dpad, after debouncing
+    debounced: u16,
+    debounced_dpad: u8,
+    // when each of those bits, and the dpad, last changed
+    bit_changed: [Option<Instant>; 16],
+    dpad_changed: Option<Instant>,
+    // per button turbo phase while a turbo button is held
+    turbo: [Option<Turbo>; BUTTON_COUNT],
+    // per button time a repeat button's keys were last sent down, while it's held
+    repeats: [Option<Instant>; BUTTON_COUNT],
+    // per button press time while a button with a hold action is down
+    holds: [Option<Hold>; BUTTON_COUNT],
+    // buttons whose toggle actions are engaged. Left alone by
+    // clear_state, only release_all lets them go.
+    toggled: [bool; BUTTON_COUNT],
+    // index of the chord currently held, if any
+    chord: Option<usize>,
+    // buttons and extra bits last seen, to catch a chord being entered
+    chord_held: u16,
+    // chord buttons kept from doing anything until they're let go
+    suppressed: u16,
+    // whether the panic buttons were all down last time
+    panic_held: bool,
+    // index of the stick chord currently held, if any
+    stick_chord: Option<usize>,
+    // each physical stick's click and pushed directions (Dpad bits) last
+    // seen, to catch a stick chord being entered
+    stick_held: [(bool, u8); 2],
+    // buttons held when last seen as bits by Button, with the dpad as its
+    // one position
+    sequence_held: u32,
+    // recent presses, oldest first, with what was held just after and when
+    sequence_log: VecDeque<(Button, u32, Instant)>,
+    // which buttons currently have their actions pressed
+    pressed: [bool; BUTTON_COUNT],
+    // set by clear_state, makes the next update check every button against
+    // `pressed` rather than only the ones whose bits changed
+    resync: bool,
+    // how many pressed buttons are holding each action, so combos sharing
+    // a modifier don't let go of it early
+    held: [u8; ACTION_COUNT],
+    // read time of the input being handled
+    read_at: Instant,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Turbo {
+    // when the button went down, the phase is worked out from this
+    since: Instant,
+    // whether the key is currently sent as down
+    down: bool,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Hold {
+    since: Instant,
+    // whether the hold actions have been pressed
+    fired: bool,
+}
+
+impl State {
+    fn new() -> State {
+        State {
+            buttons: 0,
+            extra: 0,
+            dpad: 0,
+            dpad_raw: 0,
+            socd_last: 0,
+            sticks: [(0, 0); 2],
+            stick_keys: [0; 2],
+            scroll_tick: [0; 2],
+            scroll_ramp: [None; 2],
+            unused: 0,
+            home: false,
+            home_since: None,
+            debounced: 0,
+            debounced_dpad: Input::default().dpad,
+            bit_changed: [None; 16],
+            dpad_changed: None,
+            turbo: [None; BUTTON_COUNT],
+            repeats: [None; BUTTON_COUNT],
+            holds: [None; BUTTON_COUNT],
+            toggled: [false; BUTTON_COUNT],
+            chord: None,
+            chord_held: 0,
+            suppressed: 0,
+            panic_held: false,
+            stick_chord: None,
+            stick_held: [(false, 0); 2],
+            sequence_held: 0,
+            sequence_log: VecDeque::new(),
+            pressed: [false; BUTTON_COUNT],
+            held: [0; ACTION_COUNT],
+            resync: false,
+            read_at: Instant::now(),
+        }
+    }
+
+    // Presses a button's actions in order. Ones another button already holds
+    // aren't pressed again, unless this is a resync of an already pressed button.
+    fn press<S: OutputSink>(&mut self, button: Button, actions: &[Action], sink: &mut S) -> Result<(), tfc::Error> {
+        let resync = self.pressed[button as usize];
+
+        for &action in actions {
+            let held = &mut self.held[action_index(action)];
+            // a media key is a one-off tap, resending it would turn the volume again
+            let send = if resync { !matches!(action, Action::Media(_)) } else { *held == 0 };
+            if send { action_down(action, Cause::Button(button), self.read_at, sink)?; }
+            if !resync { *held += 1; }
+        }
+
+        self.pressed[button as usize] = true;
+        Ok(())
+    }
+
+    // Releases a button's actions in reverse order, each only once nothing else holds it.
+    // Resyncs of an unpressed button leave the mouse alone so a real mouse drag isn't cut short.
+    fn release<S: OutputSink>(&mut self, button: Button, actions: &[Action], sink: &mut S) -> Result<(), tfc::Error> {
+        let was_pressed = self.pressed[button as usize];
+
+        for &action in actions.iter().rev() {
+            let held = &mut self.held[action_index(action)];
+            if was_pressed { *held = held.saturating_sub(1); }
+
+            let resend = was_pressed || matches!(action, Action::Key(_));
+            if *held == 0 && resend { action_up(action, Cause::Button(button), self.read_at, sink)?; }
+        }
+
+        self.pressed[button as usize] = false;
+        Ok(())
+    }
+
+    // For what isn't a Button, the WASD stick, chords and the report's last
+    // byte. Like press and release, an action only goes down when nothing
+    // else holds it and only up once nothing does, so a key bound here and
+    // to a button stays down until both let go.
+    fn hold_actions<S: OutputSink>(&mut self, actions: &[Action], cause: Cause, sink: &mut S) -> Result<(), tfc::Error> {
+        for &action in actions {
+            let held = &mut self.held[action_index(action)];
+            if *held == 0 { action_down(action, cause, self.read_at, sink)?; }
+            *held += 1;
+        }
+
+        Ok(())
+    }
+
+    // Key up for anything still counted as held, whatever's holding it, for
+    // when the counts can't be trusted to reach zero on their own
+    fn release_held<S: OutputSink>(&mut self, cause: Cause, sink: &mut S) -> Result<(), tfc::Error> {
+        for i in 0..ACTION_COUNT {
+            if self.held[i] == 0 { continue; }
+            self.held[i] = 0;
+
+            // the other way round from action_index
+            let action = if i < Key::COUNT as usize { Key::from_u8(i as u8).map(Action::Key) }
+                else { MouseButton::from_u8((i - Key::COUNT as usize) as u8).map(Action::Mouse) };
+            if let Some(action) = action { action_up(action, cause, self.read_at, sink)?; }
+        }
+
+        Ok(())
+    }
+
+    fn release_actions<S: OutputSink>(&mut self, actions: &[Action], cause: Cause, sink: &mut S) -> Result<(), tfc::Error> {
+        for &action in actions.iter().rev() {
+            let held = &mut self.held[action_index(action)];
+            *held = held.saturating_sub(1);
+            if *held == 0 { action_up(action, cause, self.read_at, sink)?; }
+        }
+
+        Ok(())
+    }
+
+    // A key that's already held stays held rather than being let go by the tap
+    fn tap_actions<S: OutputSink>(&mut self, actions: &[Action], cause: Cause, sink: &mut S) -> Result<(), tfc::Error> {
+        self.hold_actions(actions, cause, sink)?;
+        self.release_actions(actions, cause, sink)
+    }
+
+    // Holds or releases actions whose bit changed, returns the bit as held
+    fn check_actions<S: OutputSink>(&mut self, input: u8, diff: u8, bit: u8, actions: &[Action], cause: Cause, sink: &mut S) -> Result<u8, tfc::Error> {
+        let d = input & bit;
+
+        if diff & bit != 0 {
+            if d != 0 { self.hold_actions(actions, cause, sink)?; }
+            else { self.release_actions(actions, cause, sink)?; }
+        }
+
+        Ok(d)
+    }
+
+    // While held the actions alternate down/up every half_period, starting
+    // down on the press. Letting go releases them straight away if needed.
+    // Waits while the button is down. Past hold_time the hold actions go down
+    // and stay down until it's let go, otherwise letting go taps the tap actions.
+    fn hold<S: OutputSink>(&mut self, held: bool, button: Button, tap: &[Action], hold: &[Action], hold_time: Duration, sink: &mut S) -> Result<(), tfc::Error> {
+        match (held, self.holds[button as usize]) {
+            (true, None) => self.holds[button as usize] = Some(Hold { since: Instant::now(), fired: false }),
+            (true, Some(h)) => {
+                if !h.fired && h.since.elapsed() >= hold_time {
+                    self.press(button, hold, sink)?;
+                    self.holds[button as usize] = Some(Hold { fired: true, ..h });
+                }
+            },
+            (false, Some(h)) => {
+                if h.fired { self.release(button, hold, sink)?; }
+                else {
+                    self.press(button, tap, sink)?;
+                    self.release(button, tap, sink)?;
+                }
+                self.holds[button as usize] = None;
+            },
+            (false, None) => {},
+        }
+
+        Ok(())
+    }
+
+    // Each press flips the actions between held and released, letting go of
+    // the button does nothing. A resync puts engaged actions back down in
+    // case a send was lost.
+    fn toggle<S: OutputSink>(&mut self, pressed: bool, button: Button, actions: &[Action], sink: &mut S) -> Result<(), tfc::Error> {
+        let toggled = self.toggled[button as usize];
+
+        if pressed {
+            if toggled { self.release(button, actions, sink)?; }
+            else { self.press(button, actions, sink)?; }
+            self.toggled[button as usize] = !toggled;
+        } else if self.resync && toggled {
+            self.press(button, actions, sink)?;
+        }
+
+        Ok(())
+    }
+
+    // Forgets buttons still waiting to become a tap or hold, so letting
+    // everything go doesn't send taps nobody asked for
+    fn cancel_holds(&mut self) {
+        for hold in self.holds.iter_mut() {
+            if let Some(Hold { fired: false, .. }) = hold { *hold = None; }
+        }
+    }
+
+    fn turbo<S: OutputSink>(&mut self, held: bool, button: Button, actions: &[Action], half_period: Duration, sink: &mut S) -> Result<(), tfc::Error> {
+        match (held, self.turbo[button as usize]) {
+            (true, None) => {
+                self.press(button, actions, sink)?;
+                self.turbo[button as usize] = Some(Turbo { since: Instant::now(), down: true });
+            },
+            (true, Some(t)) => {
+                let phase = t.since.elapsed().as_nanos() / half_period.as_nanos();
+                let down = phase.is_multiple_of(2);
+
+                if down != t.down {
+                    if down { self.press(button, actions, sink)?; }
+                    else { self.release(button, actions, sink)?; }
+
+                    self.turbo[button as usize] = Some(Turbo { down, ..t });
+                }
+            },
+            (false, Some(t)) => {
+                if t.down { self.release(button, actions, sink)?; }
+                self.turbo[button as usize] = None;
+            },
+            (false, None) => {},
+        }
+
+        Ok(())
+    }
+
+    // Unlike turbo the keys never go up while the button is held, they're
+    // only sent down again every interval. Mouse buttons and media keys
+    // aren't, a second down would be a second click or tap. Letting go
+    // releases them once.
+    fn repeat<S: OutputSink>(&mut self, held: bool, button: Button, actions: &[Action], interval: Duration, sink: &mut S) -> Result<(), tfc::Error> {
+        match (held, self.repeats[button as usize]) {
+            (true, None) => {
+                self.press(button, actions, sink)?;
+                self.repeats[button as usize] = Some(Instant::now());
+            },
+            (true, Some(sent)) => {
+                if sent.elapsed() >= interval {
+                    for &action in actions.iter().filter(|action| matches!(action, Action::Key(_))) {
+                        action_down(action, Cause::Button(button), self.read_at, sink)?;
+                    }
+                    self.repeats[button as usize] = Some(Instant::now());
+                }
+            },
+            (false, Some(_)) => {
+                self.release(button, actions, sink)?;
+                self.repeats[button as usize] = None;
+            },
+            (false, None) => {},
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct Controller {
+    state: State,
+    deadzone: i32,
+    // resting stick positions, what counts as centered
+    calibration: Calibration,
+    // per physical axis, lx ly rx ry
+    invert: [bool; 4],
+    // left stick does the right stick's job and the other way round
+    swap_sticks: bool,
+    // the active profile's, left then right
+    stick_modes: [StickMode; 2],
+    mouse_curve: MouseCurve,
+    // used by MouseCurve::Power
+    mouse_exponent: f32,
+    // pixels per poll at full deflection
+    mouse_speed: f32,
+    // lines per scroll step at full deflection
+    scroll_speed: i32,
+    scroll_ramp: f32,
+    scroll_ramp_max: f32,
+    mouse_smoothing: bool,
+    normalize_diagonals: bool,
+    // with mouse_rate, the cursor and scrolling only move in tick
+    timed_sticks: bool,
+    // ticks or polls between scroll steps
+    scroll_interval: u8,
+    // fractional pixels not yet moved on each stick's axes, only used with smoothing
+    mouse_residual: [[f32; 2]; 2],
+    // copy of the active profile's mapping
+    mapping: Mapping,
+    profiles: Vec<Profile>,
+    active: usize,
+    // time a turbo key spends down, and then up, per press
+    turbo_half_period: Duration,
+    // time between a repeat key being sent down and sent down again
+    repeat_interval: Duration,
+    socd: Socd,
+    dpad_mode: DpadMode,
+    // pressure at which an analog trigger counts as pressed
+    trigger_threshold: u8,
+    hold_time: Duration,
+    // profile to start on and fall back to, picked for the device
+    base: usize,
+    quit_hold: Option<Duration>,
+    debounce: Option<Duration>,
+    // set once Home has been held for quit_hold
+    quit: bool,
+    // each chord's buttons as report bits (see report_bit), by priority
+    chords: Vec<(u16, Vec<Action>)>,
+    // report bits of the panic buttons, 0 if there aren't any
+    panic_bits: u16,
+    // report bit of the slow_button, 0 if there isn't one
+    slow_bit: u16,
+    slow_factor: f32,
+    // kept here rather than in State, so it lasts through a panic and
+    // switching profile
+    slow: bool,
+    slow_held: bool,
+    // copy of the active profile's stick chords
+    stick_chords: Vec<StickChord>,
+    // copy of the active profile's sequences
+    sequences: Vec<Sequence>,
+    // actions for each bit of Input::unused
+    unused_bits: [Vec<Action>; 8],
+}
+
+impl Controller {
+    pub fn new(config: &Config, profile: usize) -> Controller {
+        Controller {
+            state: State::new(),
+            deadzone: config.profiles[profile].deadzone,
+            calibration: config.calibration,
+            invert: config.invert,
+            swap_sticks: config.swap_sticks,
+            stick_modes: [config.profiles[profile].lstick_mode, config.profiles[profile].rstick_mode],
+            mouse_curve: config.mouse_curve,
+            mouse_exponent: config.mouse_exponent,
+            mouse_speed: config.profiles[profile].mouse_speed,
+            scroll_speed: config.profiles[profile].scroll_speed,
+            scroll_ramp: config.scroll_ramp,
+            scroll_ramp_max: config.scroll_ramp_max,
+            mouse_smoothing: config.mouse_smoothing,
+            normalize_diagonals: config.normalize_diagonals,
+            timed_sticks: config.mouse_rate.is_some(),
+            scroll_interval: match config.mouse_rate {
+                Some(rate) => ((rate * SCROLL_STEP.as_secs_f32()).round() as u8).max(1),
+                None => SCROLL_INTERVAL,
+            },
+            mouse_residual: [[0.0; 2]; 2],
+            mapping: config.profiles[profile].mapping.clone(),
+            profiles: config.profiles.clone(),
+            active: profile,
+            turbo_half_period: Duration::from_secs_f32(0.5 / config.turbo_rate),
+            repeat_interval: Duration::from_secs_f32(1.0 / config.repeat_rate),
+            socd: config.socd,
+            dpad_mode: config.dpad_mode,
+            trigger_threshold: config.trigger_threshold,
+            hold_time: config.hold_time,
+            base: profile,
+            quit_hold: config.quit_hold,
+            debounce: config.debounce,
+            quit: false,
+            chords: config.chords.iter()
+                .map(|chord| (chord.buttons.iter().fold(0, |bits, &b| bits | report_bit(b)), chord.actions.clone()))
+                .collect(),
+            panic_bits: config.panic_buttons.iter().fold(0, |bits, &b| bits | report_bit(b)),
+            slow_bit: config.slow_button.map_or(0, report_bit),
+            slow_factor: config.slow_factor,
+            slow: false,
+            slow_held: false,
+            stick_chords: config.profiles[profile].stick_chords.clone(),
+            sequences: config.profiles[profile].sequences.clone(),
+            unused_bits: config.unused_bits.clone(),
+        }
+    }
+
+    // Has the next update fix up any button whose output doesn't match the
+    // input, e.g. after a send failed part way. Buttons that are already
+    // right are left alone, so steady input never sees a key up/down.
+    pub fn clear_state(&mut self) {
+        self.state.resync = true;
+    }
+
+    // Everything one report causes goes out in one flush of the sink, even
+    // if some of it failed
+    pub fn update<S: OutputSink>(&mut self, input: Input, sink: &mut S) -> Result<(), tfc::Error> {
+        let result = self._update(input, sink);
+        let flushed = sink.flush();
+        result.and(flushed)
+    }
+
+    fn _update<S: OutputSink>(&mut self, input: Input, sink: &mut S) -> Result<(), tfc::Error> {
+        self.state.read_at = input.read_at;
+        let input = self._debounce(input);
+        if self._handle_panic(&input, sink)? { return Ok(()); }
+        let input = self._handle_slow(input);
+        self._handle_sequences(&input, sink)?;
+        let input = self._handle_stick_chords(input, sink)?;
+        let input = self._handle_chords(input, sink)?;
+
+        let home = input.extra & Extra::Home as u8 != 0;
+        match self.quit_hold {
+            Some(quit_hold) => self._handle_quit_hold(home, quit_hold, sink)?,
+            None => if home && !self.state.home && self._cycles_profiles() { self._next_profile(sink)?; },
+        }
+        self.state.home = home;
+
+        self._apply(input, sink)
+    }
+
+    // When the panic buttons go down together, lets go of everything and
+    // starts State over, even keys it's lost track of. Whatever was held
+    // then does nothing until it's let go, so the buttons can't press their
+    // keys straight back down. Returns whether that happened, the report
+    // having done its job.
+    fn _handle_panic<S: OutputSink>(&mut self, input: &Input, sink: &mut S) -> Result<bool, tfc::Error> {
+        if self.panic_bits == 0 { return Ok(false); }
+
+        let held = input.buttons as u16 | (input.extra as u16) << 8;
+        let down = held & self.panic_bits == self.panic_bits;
+        let entered = down && !self.state.panic_held;
+        self.state.panic_held = down;
+        if !entered { return Ok(false); }
+
+        warn!("Panic buttons pressed, letting go of everything");
+        self._reset(held, Cause::Panic, sink)?;
+        self.state.panic_held = true;
+
+        Ok(true)
+    }
+
+    // What the panic buttons do, for anything else wanting the same. `input`
+    // is the last report, whose buttons then do nothing until let go.
+    pub fn release_everything<S: OutputSink>(&mut self, input: &Input, sink: &mut S) -> Result<(), tfc::Error> {
+        let held = input.buttons as u16 | (input.extra as u16) << 8;
+        let panic_held = self.state.panic_held;
+        let result = self._reset(held, Cause::Control, sink);
+        self.state.panic_held = panic_held;
+
+        let flushed = sink.flush();
+        result.and(flushed)
+    }
+
+    // Lets go of everything, even keys State has lost track of, and starts
+    // State over. `held` is report bits to ignore until they're let go.
+    fn _reset<S: OutputSink>(&mut self, held: u16, cause: Cause, sink: &mut S) -> Result<(), tfc::Error> {
+        self.release_all(sink)?;
+        self.state.release_held(cause, sink)?;
+
+        let read_at = self.state.read_at;
+        self.state = State::new();
+        self.state.read_at = read_at;
+        self.state.suppressed = held;
+        // chord_held too, so a chord among them isn't entered by the next report
+        self.state.chord_held = held;
+
+        Ok(())
+    }
+
+    // Each press of the slow_button turns slow mode on or off. It's taken
+    // out of the input either way, so nothing after sees it held.
+    fn _handle_slow(&mut self, mut input: Input) -> Input {
+        if self.slow_bit == 0 { return input; }
+
+        let held = input.buttons as u16 | (input.extra as u16) << 8;
+        let down = held & self.slow_bit != 0;
+        if down && !self.slow_held {
+            self.slow = !self.slow;
+            if self.slow { info!("Slow mode on, the sticks, turbo and repeat at {}x speed", self.slow_factor); }
+            else { info!("Slow mode off"); }
+        }
+        self.slow_held = down;
+
+        input.buttons &= !self.slow_bit as u8;
+        input.extra &= !(self.slow_bit >> 8) as u8;
+        input
+    }
+
+    // How much slow mode scales speeds by, 1 when it's off
+    fn _speed(&self) -> f32 {
+        if self.slow { self.slow_factor } else { 1.0 }
+    }
+
+    // For driving a Controller without run, from reports read some other
+    // way. Takes one raw HORIPAD report with no report id byte in front.
+    pub fn process<S: OutputSink>(&mut self, report: &[u8], sink: &mut S) -> Result<(), Error> {
+        let input = Input::parse(report, None).map_err(Error::Report)?;
+        Ok(self.update(input, sink)?)
+    }
+
+    // With mouse_rate, moves the cursor and scrolls from where the sticks
+    // were last seen. Meant to be called that many times a second, however
+    // often reports come in, so the cursor moves evenly even on a pad that
+    // reports slowly. Does nothing otherwise.
+    pub fn tick<S: OutputSink>(&mut self, sink: &mut S) -> Result<(), tfc::Error> {
+        if !self.timed_sticks { return Ok(()); }
+
+        self.state.read_at = Instant::now();
+        let [lstick, rstick] = self.state.sticks;
+        let result = self._handle_stick(0, lstick, true, sink).and_then(|()| self._handle_stick(1, rstick, true, sink));
+        let flushed = sink.flush();
+        result.and(flushed)
+    }
+
+    // Whether Home has been held long enough to shut down
+    pub fn quit_requested(&self) -> bool {
+        self.quit
+    }
+
+    // Home does nothing while it's down, so holding it to quit doesn't send
+    // anything first. Let go sooner and it cycles profiles or taps its key
+    // as it normally would. A chord taking Home over cancels it.
+    fn _handle_quit_hold<S: OutputSink>(&mut self, home: bool, quit_hold: Duration, sink: &mut S) -> Result<(), tfc::Error> {
+        if self.state.suppressed & report_bit(Button::Home) != 0 {
+            self.state.home_since = None;
+            return Ok(());
+        }
+
+        match (home, self.state.home_since) {
+            (true, None) => self.state.home_since = Some(Instant::now()),
+            (true, Some(since)) => if since.elapsed() >= quit_hold { self.quit = true; },
+            (false, Some(since)) => {
+                self.state.home_since = None;
+                if since.elapsed() >= quit_hold { return Ok(()); }
+
+                if self._cycles_profiles() { self._next_profile(sink)?; }
+                else if let Some(text) = &self.mapping[Button::Home].text { type_text(text, Button::Home, sink); }
+                else {
+                    self.state.press(Button::Home, &self.mapping[Button::Home].actions, sink)?;
+                    self.state.release(Button::Home, &self.mapping[Button::Home].actions, sink)?;
+                }
+            },
+            (false, None) => {},
+        }
+
+        Ok(())
+    }
+
+    // Sends key up for everything the current state has down. Resyncing first
+    // makes sure a pressed button is let go even if its state bit is stale.
+    pub fn release_all<S: OutputSink>(&mut self, sink: &mut S) -> Result<(), tfc::Error> {
+        self.state.read_at = Instant::now();
+        self.state.cancel_holds();
+        self._release_toggles(sink)?;
+        self.clear_state();
+        let result = self._apply(Input::at_rest(&self.calibration), sink);
+        let flushed = sink.flush();
+        result.and(flushed)
+    }
+
+    // Letting the buttons go can't undo a toggle, so it's done here
+    fn _release_toggles<S: OutputSink>(&mut self, sink: &mut S) -> Result<(), tfc::Error> {
+        for &button in &BUTTONS {
+            if self.state.toggled[button as usize] {
+                self.state.release(button, &self.mapping[button].actions, sink)?;
+                self.state.toggled[button as usize] = false;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn _apply<S: OutputSink>(&mut self, input: Input, sink: &mut S) -> Result<(), tfc::Error> {
+        let buttons = self._trigger_buttons(input.buttons, input.triggers);
+
+        self.state.buttons = self._handle_buttons(buttons, sink)?;
+        self.state.extra = self._handle_extra(input.extra, sink)?;
+        self.state.dpad = self._handle_dpad(input.dpad, sink)?;
+        let (lstick, rstick) = self._sticks(&input);
+        self.state.sticks = [lstick, rstick];
+        self._handle_stick(0, lstick, false, sink)?;
+        self._handle_stick(1, rstick, false, sink)?;
+        self.state.unused = self._handle_unused(input.unused, sink)?;
+        self.state.resync = false;
+
+        Ok(())
+    }
+
+    // A change is taken straight away, so debouncing adds no latency to a
+    // clean press or release. Any further change of the same button inside
+    // the debounce window is ignored, which swallows the flicker of a worn
+    // contact. Once the window is up the button follows the input again.
+    // The dpad is one value, so it's treated as a single button.
+    fn _debounce(&mut self, mut input: Input) -> Input {
+        let window = match self.debounce {
+            Some(window) => window,
+            None => return input,
+        };
+        let settled = |changed: Option<Instant>| changed.is_none_or(|t| t.elapsed() >= window);
+
+        let held = input.buttons as u16 | (input.extra as u16) << 8;
+        for bit in 0..16 {
+            let mask = 1 << bit;
+            if (held ^ self.state.debounced) & mask != 0 && settled(self.state.bit_changed[bit]) {
+                self.state.debounced ^= mask;
+                self.state.bit_changed[bit] = Some(Instant::now());
+            }
+        }
+
+        if input.dpad != self.state.debounced_dpad && settled(self.state.dpad_changed) {
+            self.state.debounced_dpad = input.dpad;
+            self.state.dpad_changed = Some(Instant::now());
+        }
+
+        input.buttons = self.state.debounced as u8;
+        input.extra = (self.state.debounced >> 8) as u8;
+        input.dpad = self.state.debounced_dpad;
+        input
+    }
+
+    // A chord taps its actions once when the last of its buttons goes down.
+    // Its buttons are then masked out of the input until each is let go, so
+    // their own keys are released and stay quiet. Only one chord is held at
+    // a time and the first by priority wins if several are entered at once.
+    fn _handle_chords<S: OutputSink>(&mut self, mut input: Input, sink: &mut S) -> Result<Input, tfc::Error> {
+        let held = input.buttons as u16 | (input.extra as u16) << 8;
+        let entered = |bits: u16, prev: u16| held & bits == bits && prev & bits != bits;
+
+        if let Some(i) = self.state.chord {
+            if held & self.chords[i].0 != self.chords[i].0 { self.state.chord = None; }
+        }
+
+        if self.state.chord.is_none() {
+            let prev = self.state.chord_held;
+
+            if let Some(i) = self.chords.iter().position(|(bits, _)| entered(*bits, prev)) {
+                self.state.tap_actions(&self.chords[i].1, Cause::Chord(i), sink)?;
+
+                self.state.chord = Some(i);
+                self.state.suppressed |= self.chords[i].0;
+            }
+        }
+
+        self.state.chord_held = held;
+        self.state.suppressed &= held;
+
+        input.buttons &= !self.state.suppressed as u8;
+        input.extra &= !(self.state.suppressed >> 8) as u8;
+        Ok(input)
+    }
+
+    // Each new press is logged, and the first sequence by priority whose
+    // last step it is, with the rest pressed in order before it within the
+    // window, taps its actions. Other presses in between don't spoil one.
+    // The presses still send their own actions. The log starts over after
+    // a sequence fires so its steps can't finish another.
+    fn _handle_sequences<S: OutputSink>(&mut self, input: &Input, sink: &mut S) -> Result<(), tfc::Error> {
+        if self.sequences.is_empty() { return Ok(()); }
+
+        let buttons = self._trigger_buttons(input.buttons, input.triggers);
+        let held = sequence_bits(buttons, input.extra, input.dpad);
+        let pressed = held & !self.state.sequence_held;
+        self.state.sequence_held = held;
+        if pressed == 0 { return Ok(()); }
+
+        let now = input.read_at;
+        let window = self.sequences.iter().map(|sequence| sequence.window).max().unwrap_or_default();
+        let log = &mut self.state.sequence_log;
+        while log.front().is_some_and(|&(_, _, at)| now.saturating_duration_since(at) > window) { log.pop_front(); }
+
+        for &button in BUTTONS.iter().filter(|&&button| pressed & 1 << button as usize != 0) {
+            if log.len() == SEQUENCE_LOG_LEN { log.pop_front(); }
+            log.push_back((button, held, now));
+        }
+
+        if let Some(i) = self.sequences.iter().position(|sequence| sequence_completed(sequence, &self.state.sequence_log, now)) {
+            self.state.tap_actions(&self.sequences[i].actions, Cause::Sequence(i), sink)?;
+            self.state.sequence_log.clear();
+        }
+
+        Ok(())
+    }
+
+    // A stick chord taps its actions once when its stick is pushed its way
+    // while clicked, whichever comes last. While it's held the stick reads as
+    // centered, and the click is masked out like a chord's buttons until
+    // it's let go, so neither sends anything of its own. Runs before
+    // _handle_chords, which does the masking. The first listed wins if
+    // several are entered at once.
+    fn _handle_stick_chords<S: OutputSink>(&mut self, mut input: Input, sink: &mut S) -> Result<Input, tfc::Error> {
+        if self.stick_chords.is_empty() { return Ok(input); }
+
+        let (lstick, rstick) = self._physical_sticks(&input);
+        let held = [
+            (input.extra & Extra::LSB as u8 != 0, stick_directions(lstick)),
+            (input.extra & Extra::RSB as u8 != 0, stick_directions(rstick)),
+        ];
+        let met = |chord: &StickChord, sticks: &[(bool, u8); 2]| {
+            let (clicked, directions) = sticks[stick_index(chord.click)];
+            clicked && directions & direction_bit(chord.direction) != 0
+        };
+
+        if let Some(i) = self.state.stick_chord {
+            if !met(&self.stick_chords[i], &held) { self.state.stick_chord = None; }
+        }
+
+        if self.state.stick_chord.is_none() {
+            let prev = self.state.stick_held;
+
+            if let Some(i) = self.stick_chords.iter().position(|chord| met(chord, &held) && !met(chord, &prev)) {
+                self.state.tap_actions(&self.stick_chords[i].actions, Cause::StickChord(i), sink)?;
+
+                self.state.stick_chord = Some(i);
+                self.state.suppressed |= report_bit(self.stick_chords[i].click);
+            }
+        }
+
+        self.state.stick_held = held;
+
+        if let Some(i) = self.state.stick_chord {
+            let Calibration { lstick, rstick } = self.calibration;
+            match self.stick_chords[i].click {
+                Button::LSB => { input.lstick1 = lstick[0]; input.lstick2 = lstick[1]; },
+                _ => { input.rstick1 = rstick[0]; input.rstick2 = rstick[1]; },
+            }
+        }
+
+        Ok(input)
+    }
+
+    // The physical input, not what it's mapped to
+    pub fn snapshot(&self, input: &Input) -> Snapshot {
+        let buttons = input.pressed().iter().map(|button| format!("{:?}", button)).collect();
+
+        Snapshot {
+            profile: self.profiles[self.active].name.clone(),
+            buttons,
+            lstick: [input.lstick1, input.lstick2],
+            rstick: [input.rstick1, input.rstick2],
+            triggers: input.triggers.map(|(zl, zr)| [zl, zr]),
+        }
+    }
+
+    // With more than one profile Home cycles through them instead of sending its key
+    fn _cycles_profiles(&self) -> bool {
+        self.profiles.len() > 1
+    }
+
+    fn _next_profile<S: OutputSink>(&mut self, sink: &mut S) -> Result<(), tfc::Error> {
+        self._switch_profile((self.active + 1) % self.profiles.len(), sink)?;
+        info!("Switched to profile {:?}", self.profiles[self.active].name);
+
+        Ok(())
+    }
+
+    // Picks the profile listing the focused app, or the device's own profile
+    // if none do. Home can still cycle away until the app changes again.
+    pub fn select_app<S: OutputSink>(&mut self, app: Option<&str>, sink: &mut S) -> Result<(), tfc::Error> {
+        let index = app
+            .and_then(|app| self.profiles.iter().position(|profile| profile.apps.iter().any(|a| a == app)))
+            .unwrap_or(self.base);
+        if index == self.active { return Ok(()); }
+
+        self._switch_profile(index, sink)?;
+        info!("Switched to profile {:?} for {}", self.profiles[self.active].name, app.unwrap_or("an unknown app"));
+
+        Ok(())
+    }
+
+    // Switches to the profile at index, if there is one, until Home cycles
+    // away or the focused app changes
+    pub fn select_profile<S: OutputSink>(&mut self, index: usize, sink: &mut S) -> Result<(), tfc::Error> {
+        if index >= self.profiles.len() || index == self.active { return Ok(()); }
+
+        self._switch_profile(index, sink)?;
+        info!("Switched to profile {:?}", self.profiles[self.active].name);
+
+        Ok(())
+    }
+
+    fn _switch_profile<S: OutputSink>(&mut self, index: usize, sink: &mut S) -> Result<(), tfc::Error> {
+        self.release_all(sink)?;
+
+        self.active = index;
+        let profile = &self.profiles[self.active];
+        self.mapping = profile.mapping.clone();
+        self.mouse_speed = profile.mouse_speed;
+        self.scroll_speed = profile.scroll_speed;
+        self.deadzone = profile.deadzone;
+        self.stick_chords = profile.stick_chords.clone();
+        self.state.stick_chord = None;
+        self.sequences = profile.sequences.clone();
+        self.state.sequence_log.clear();
+        self.stick_modes = [profile.lstick_mode, profile.rstick_mode];
+        self.mouse_residual = [[0.0; 2]; 2];
+
+        Ok(())
+    }
+
+    fn _handle_buttons<S: OutputSink>(&mut self, buttons: u8, sink: &mut S) -> Result<u8, tfc::Error> {
+        let mut s: u8 = 0;
+        let diff = buttons ^ self.state.buttons;
+
+        s |= self._check_button(buttons, diff, Buttons::Y as u8, Button::Y, sink)?;
+        s |= self._check_button(buttons, diff, Buttons::B as u8, Button::B, sink)?;
+        s |= self._check_button(buttons, diff, Buttons::A as u8, Button::A, sink)?;
+        s |= self._check_button(buttons, diff, Buttons::X as u8, Button::X, sink)?;
+        s |= self._check_button(buttons, diff, Buttons::L as u8, Button::L, sink)?;
+        s |= self._check_button(buttons, diff, Buttons::R as u8, Button::R, sink)?;
+        s |= self._check_button(buttons, diff, Buttons::ZL as u8, Button::ZL, sink)?;
+        s |= self._check_button(buttons, diff, Buttons::ZR as u8, Button::ZR, sink)?;
+
+        Ok(s)
+    }
+
+    fn _handle_extra<S: OutputSink>(&mut self, extra: u8, sink: &mut S) -> Result<u8, tfc::Error> {
+        let mut s: u8 = 0;
+        let diff = extra ^ self.state.extra;
+
+        s |= self._check_button(extra, diff, Extra::Minus as u8, Button::Minus, sink)?;
+        s |= self._check_button(extra, diff, Extra::Plus as u8, Button::Plus, sink)?;
+        s |= self._check_button(extra, diff, Extra::LSB as u8, Button::LSB, sink)?;
+        s |= self._check_button(extra, diff, Extra::RSB as u8, Button::RSB, sink)?;
+        if !self._cycles_profiles() && self.quit_hold.is_none() {
+            s |= self._check_button(extra, diff, Extra::Home as u8, Button::Home, sink)?;
+        }
+
+        Ok(s)
+    }
+
+    // Releases go before presses, so moving between a diagonal and a
+    // cardinal never has both down at once
+    fn _handle_dpad<S: OutputSink>(&mut self, dpad: u8, sink: &mut S) -> Result<u8, tfc::Error> {
+        let mut s: u8 = 0;
+        let cleaned = convert_dpad(dpad) as u8;
+        let cleaned = match self.dpad_mode {
+            DpadMode::Cardinals => {
+                let cleaned = self._resolve_socd(cleaned);
+                self._bound_diagonal(cleaned)
+            },
+            DpadMode::Octants => octant_bit(cleaned),
+        };
+        let diff = cleaned ^ self.state.dpad;
+
+        for &pressing in &[false, true] {
+            for &(bit, button) in &DPAD_BUTTONS {
+                if (cleaned & bit != 0) == pressing { s |= self._check_button(cleaned, diff, bit, button, sink)?; }
+            }
+        }
+
+        Ok(s)
+    }
+
+    // A diagonal with its own binding is sent as its DIAGONAL_BITS bit
+    // instead of the two cardinals
+    fn _bound_diagonal(&self, dpad: u8) -> u8 {
+        DIAGONAL_BITS.iter()
+            .find(|&&(diagonal, _, button)| dpad == diagonal as u8 && self.mapping[button].bound())
+            .map_or(dpad, |&(_, bit, _)| bit)
+    }
+
+    // Stick 0 is the left one and 1 the right, after swap_sticks. A mode the
+    // stick isn't in leaves its state alone, which is all at rest anyway since
+    // the profile switch that changed modes released everything first. With
+    // mouse_rate, moving the cursor and scrolling only happen when `ticking`
+    // and WASD keys only when not, so keys still follow each report.
+    fn _handle_stick<S: OutputSink>(&mut self, stick: usize, (x, y): (i32, i32), ticking: bool, sink: &mut S) -> Result<(), tfc::Error> {
+        let mode = self.stick_modes[stick];
+        let timed = self.timed_sticks && matches!(mode, StickMode::Mouse | StickMode::Scroll);
+        if timed != ticking { return Ok(()); }
+
+        let (x, y) = self._apply_deadzone(x, y);
+
+        match mode {
+            StickMode::Mouse => {
+                let (vx, vy) = self._stick_velocities(x, y);
+                let dx = self._mouse_delta(stick, vx, 0);
+                let dy = self._mouse_delta(stick, vy, 1);
+
+                if dx != 0 || dy != 0 { sink.mouse_move_rel(dx, dy)?; }
+            },
+            StickMode::Wasd => self.state.stick_keys[stick] = self._handle_stick_keys(stick, x, y, sink)?,
+            StickMode::Scroll => self.state.scroll_tick[stick] = self._handle_scroll(stick, x, y, sink)?,
+            StickMode::Off => {},
+        }
+
+        Ok(())
+    }
+
+    // Scrolls once as soon as the stick leaves the deadzone, then every
+    // scroll_interval polls or ticks while it stays out, faster the longer it's held
+    // if there's a ramp. Pushing it back the other way on either axis starts
+    // the ramp over and steps straight away, so reversing is never slow or
+    // fast the wrong way. Returns the updated tick.
+    fn _handle_scroll<S: OutputSink>(&mut self, stick: usize, x: i32, y: i32, sink: &mut S) -> Result<u8, tfc::Error> {
+        if x == 0 && y == 0 {
+            self.state.scroll_ramp[stick] = None;
+            return Ok(0);
+        }
+
+        let now = self.state.read_at;
+        let signs = (x.signum(), y.signum());
+        let mut tick = self.state.scroll_tick[stick];
+        let since = match self.state.scroll_ramp[stick] {
+            Some((since, (sx, sy))) if sx * signs.0 >= 0 && sy * signs.1 >= 0 => since,
+            Some(_) => {
+                tick = 0;
+                now
+            },
+            None => now,
+        };
+        // an axis that's let go keeps its old sign, until it's pushed again
+        let kept = self.state.scroll_ramp[stick].map_or((0, 0), |(_, kept)| kept);
+        let signs = (if signs.0 != 0 { signs.0 } else { kept.0 }, if signs.1 != 0 { signs.1 } else { kept.1 });
+        self.state.scroll_ramp[stick] = Some((since, signs));
+
+        if tick == 0 {
+            let held = now.saturating_duration_since(since).as_secs_f32();
+            let speed = self.scroll_speed as f32 * (1.0 + self.scroll_ramp * held).min(self.scroll_ramp_max);
+            let dx = (x as f32 * speed / 128.0) as i32;
+            let dy = (y as f32 * speed / 128.0) as i32;
+
+            if dx != 0 || dy != 0 { sink.mouse_scroll(dx, dy)?; }
+        }
+
+        // slow mode steps less often rather than by less, a step being a
+        // whole number of lines
+        let interval = (self.scroll_interval as f32 / self._speed()).round().min(u8::MAX as f32) as u8;
+        Ok((tick + 1) % interval)
+    }
+
+    // Treats each axis crossing WASD_THRESHOLD like a dpad press, so the
+    // usual diff against the last emitted directions applies and the keys
+    // follow the dpad mapping
+    fn _handle_stick_keys<S: OutputSink>(&mut self, stick: usize, x: i32, y: i32, sink: &mut S) -> Result<u8, tfc::Error> {
+        let mut s: u8 = 0;
+        let dir = stick_directions((x, y));
+        let diff = dir ^ self.state.stick_keys[stick];
+
+        s |= self.state.check_actions(dir, diff, Dpad::U as u8, &self.mapping[Button::Up].actions, Cause::Stick(stick), sink)?;
+        s |= self.state.check_actions(dir, diff, Dpad::D as u8, &self.mapping[Button::Down].actions, Cause::Stick(stick), sink)?;
+        s |= self.state.check_actions(dir, diff, Dpad::L as u8, &self.mapping[Button::Left].actions, Cause::Stick(stick), sink)?;
+        s |= self.state.check_actions(dir, diff, Dpad::R as u8, &self.mapping[Button::Right].actions, Cause::Stick(stick), sink)?;
+
+        Ok(s)
+    }
+
+    // Bits of the last report byte with a binding act like plain buttons,
+    // the rest are ignored
+    fn _handle_unused<S: OutputSink>(&mut self, unused: u8, sink: &mut S) -> Result<u8, tfc::Error> {
+        let mut s: u8 = 0;
+        let diff = unused ^ self.state.unused;
+
+        for (i, actions) in self.unused_bits.iter().enumerate().filter(|(_, actions)| !actions.is_empty()) {
+            s |= self.state.check_actions(unused, diff, 1 << i, actions, Cause::Unused(i), sink)?;
+        }
+
+        Ok(s)
+    }
+
+    // In analog mode the ZL/ZR bits come from the trigger pressure instead,
+    // so the digital handling after this works the same either way
+    fn _trigger_buttons(&self, buttons: u8, triggers: Option<(u8, u8)>) -> u8 {
+        let (zl, zr) = match triggers {
+            Some(triggers) => triggers,
+            None => return buttons,
+        };
+
+        let mut b = buttons & !(Buttons::ZL as u8 | Buttons::ZR as u8);
+        if zl >= self.trigger_threshold { b |= Buttons::ZL as u8; }
+        if zr >= self.trigger_threshold { b |= Buttons::ZR as u8; }
+
+        b
+    }
+
+    // What the left and right stick handlers should see, the physical
+    // sticks swapped if they're configured to be
+    fn _sticks(&self, input: &Input) -> ((i32, i32), (i32, i32)) {
+        let (lstick, rstick) = self._physical_sticks(input);
+        if self.swap_sticks { (rstick, lstick) } else { (lstick, rstick) }
+    }
+
+    // Each physical stick's axes centered on its calibrated center, then
+    // inverted as configured. Inverting after centering flips around the
+    // calibrated center, so a stick at rest stays at 0.
+    fn _physical_sticks(&self, input: &Input) -> ((i32, i32), (i32, i32)) {
+        let axis = |centered: i16, invert: bool| if invert { -centered as i32 } else { centered as i32 };
+        let [invert_lx, invert_ly, invert_rx, invert_ry] = self.invert;
+        let calibration = &self.calibration;
+
+        let lstick = (axis(input.lx(calibration), invert_lx), axis(input.ly(calibration), invert_ly));
+        let rstick = (axis(input.rx(calibration), invert_rx), axis(input.ry(calibration), invert_ry));
+
+        (lstick, rstick)
+    }
+
+    // Zeroes both centered axes if the combined deflection is inside the
+    // deadzone. This is radial rather than per-axis so diagonal drift gets
+    // suppressed too.
+    fn _apply_deadzone(&self, x: i32, y: i32) -> (i32, i32) {
+        if x * x + y * y < self.deadzone * self.deadzone { return (0, 0); }
+
+        (x, y)
+    }
+
+    // Pixels per poll on each axis. Normalized, the curve is applied to how
+    // far the stick is pushed in any direction and the speed split between
+    // the axes, so a full diagonal is no faster than full right. The corners
+    // of the stick's travel reach past 128, so that's capped.
+    fn _stick_velocities(&self, x: i32, y: i32) -> (f32, f32) {
+        if !self.normalize_diagonals { return (self._stick_velocity(x as f32), self._stick_velocity(y as f32)); }
+
+        let distance = ((x * x + y * y) as f32).sqrt();
+        if distance == 0.0 { return (0.0, 0.0); }
+
+        let speed = self._stick_velocity(distance.min(128.0));
+        (speed * x as f32 / distance, speed * y as f32 / distance)
+    }
+
+    // Takes a centered axis value, giving -mouse_speed..=mouse_speed pixels
+    // per poll. Every curve maps 0 to 0 and 1 to 1 and only ever rises in
+    // between, so there's no dead spot and full deflection is full speed.
+    fn _stick_velocity(&self, axis: f32) -> f32 {
+        let deflection = axis / 128.0;
+
+        let speed = match self.mouse_curve {
+            MouseCurve::Linear => deflection.abs(),
+            MouseCurve::Exponential => (MOUSE_CURVE_EXPONENT * deflection.abs()).exp_m1() / MOUSE_CURVE_EXPONENT.exp_m1(),
+            MouseCurve::Power => deflection.abs().powf(self.mouse_exponent),
+        };
+
+        speed.copysign(deflection) * self.mouse_speed * self._speed()
+    }
+
+    // Whole pixels to move this poll. Without smoothing the fraction is
+    // dropped, so deflections worth under a pixel per poll don't move at
+    // all. With it the fraction builds up until it's worth a pixel. The
+    // residual is dropped once the stick is back in the deadzone.
+    fn _mouse_delta(&mut self, stick: usize, velocity: f32, axis: usize) -> i32 {
+        if !self.mouse_smoothing { return velocity.trunc() as i32; }
+        let residual = &mut self.mouse_residual[stick][axis];
+        if velocity == 0.0 {
+            *residual = 0.0;
+            return 0;
+        }
+
+        let total = *residual + velocity;
+        let delta = total.trunc();
+        *residual = total - delta;
+
+        delta as i32
+    }
+
+    fn _check_button<S: OutputSink>(&mut self, input: u8, diff: u8, bit: u8, button: Button, sink: &mut S) -> Result<u8, tfc::Error> {
+        let binding = &self.mapping[button];
+        let held = input & bit;
+        // the edge a Trigger fires on, a press or a release
+        let fires = diff & bit != 0 && (held != 0) == (binding.trigger == Trigger::OnPress);
+
+        if let Some(text) = &binding.text {
+            if fires { type_text(text, button, sink); }
+            return Ok(held);
+        }
+
+        // only a change of the bit is an edge, so a held button fires the
+        // once when it's let go however many reports it was held for
+        if binding.trigger == Trigger::OnRelease {
+            if fires {
+                self.state.press(button, &binding.actions, sink)?;
+                self.state.release(button, &binding.actions, sink)?;
+            }
+            return Ok(held);
+        }
+
+        // toggles only act on the press
+        if binding.toggle {
+            self.state.toggle(diff & bit != 0 && held != 0, button, &binding.actions, sink)?;
+            return Ok(held);
+        }
+
+        if binding.turbo {
+            self.state.turbo(held != 0, button, &binding.actions, self.turbo_half_period.div_f32(self._speed()), sink)?;
+            return Ok(held);
+        }
+
+        if binding.repeat {
+            self.state.repeat(held != 0, button, &binding.actions, self.repeat_interval.div_f32(self._speed()), sink)?;
+            return Ok(held);
+        }
+
+        if let Some(hold) = &binding.hold {
+            self.state.hold(held != 0, button, &binding.actions, hold, self.hold_time, sink)?;
+            return Ok(held);
+        }
+
+        let stale = self.state.resync && (held != 0) != self.state.pressed[button as usize];
+
+        if diff & bit != 0 || stale {
+            if held != 0 { self.state.press(button, &binding.actions, sink)?; }
+            else { self.state.release(button, &binding.actions, sink)?; }
+        }
+
+        Ok(held)
+    }
+
+    // Settles left+right and up+down held together according to the socd
+    // mode before any keys are sent
+    fn _resolve_socd(&mut self, dpad: u8) -> u8 {
+        let pressed = dpad & !self.state.dpad_raw;
+        self.state.dpad_raw = dpad;
+
+        let mut out = dpad;
+        for &(a, b) in &[(Dpad::L as u8, Dpad::R as u8), (Dpad::U as u8, Dpad::D as u8)] {
+            if pressed & a != 0 { self.state.socd_last = (self.state.socd_last & !b) | a; }
+            if pressed & b != 0 { self.state.socd_last = (self.state.socd_last & !a) | b; }
+            if dpad & a == 0 || dpad & b == 0 { continue; }
+
+            let keep = match self.socd {
+                Socd::Neutral => 0,
+                Socd::LastInput => self.state.socd_last & (a | b),
+                Socd::UpPriority => (a | b) & Dpad::U as u8,
+            };
+            out = (out & !(a | b)) | keep;
+        }
+
+        out
+    }
+}
+
+// The actual dpad input values just increase sequentially
+// Here we convert them to a nicer bitwise format
+fn convert_dpad(dpad: u8) -> Dpad {
+    match dpad {
+        0 => Dpad::U,
+        1 => Dpad::UR,
+        2 => Dpad::R,
+        3 => Dpad::DR,
+        4 => Dpad::D,
+        5 => Dpad::DL,
+        6 => Dpad::L,
+        7 => Dpad::UL,
+        _ => Dpad::Off,
+    }
+}
+
+// Each of the 8 positions as a bit of its own, the diagonals as their
+// DIAGONAL_BITS bit. Releases go before presses in _handle_dpad, so moving
+// round lets go of one octant's key before the next one's goes down.
+fn octant_bit(dpad: u8) -> u8 {
+    DIAGONAL_BITS.iter()
+        .find(|&&(diagonal, _, _)| dpad == diagonal as u8)
+        .map_or(dpad, |&(_, bit, _)| bit)
+}
+
+// Directions a centered stick is pushed past WASD_THRESHOLD, in Dpad bits
+fn stick_directions((x, y): (i32, i32)) -> u8 {
+    let mut dir: u8 = 0;
+
+    if y <= -WASD_THRESHOLD { dir |= Dpad::U as u8; }
+    else if y >= WASD_THRESHOLD { dir |= Dpad::D as u8; }
+
+    if x <= -WASD_THRESHOLD { dir |= Dpad::L as u8; }
+    else if x >= WASD_THRESHOLD { dir |= Dpad::R as u8; }
+
+    dir
+}
+
+// Which physical stick a click belongs to, left first
+fn stick_index(click: Button) -> usize {
+    if click == Button::LSB { 0 } else { 1 }
+}
+
+fn direction_bit(direction: Button) -> u8 {
+    match direction {
+        Button::Up => Dpad::U as u8,
+        Button::Down => Dpad::D as u8,
+        Button::Left => Dpad::L as u8,
+        Button::Right => Dpad::R as u8,
+        _ => 0,
+    }
+}
+
+// Where a button sits in a report, buttons in the low byte and extra in the
+// high one. The dpad isn't a bitfield so it has no bit, and config keeps it
+// out of chords.
+fn report_bit(button: Button) -> u16 {
+    match button {
+        Button::Y => Buttons::Y as u16,
+        Button::B => Buttons::B as u16,
+        Button::A => Buttons::A as u16,
+        Button::X => Buttons::X as u16,
+        Button::L => Buttons::L as u16,
+        Button::R => Buttons::R as u16,
+        Button::ZL => Buttons::ZL as u16,
+        Button::ZR => Buttons::ZR as u16,
+        Button::Minus => (Extra::Minus as u16) << 8,
+        Button::Plus => (Extra::Plus as u16) << 8,
+        Button::LSB => (Extra::LSB as u16) << 8,
+        Button::RSB => (Extra::RSB as u16) << 8,
+        Button::Home => (Extra::Home as u16) << 8,
+        Button::Up | Button::Down | Button::Left | Button::Right
+        | Button::UpLeft | Button::UpRight | Button::DownLeft | Button::DownRight => 0,
+    }
+}
+
+// Held buttons as bits by Button, the dpad as the one of its 8 positions
+// it's in, for matching sequences
+fn sequence_bits(buttons: u8, extra: u8, dpad: u8) -> u32 {
+    let held = buttons as u16 | (extra as u16) << 8;
+    let position = match convert_dpad(dpad) {
+        Dpad::U => Some(Button::Up),
+        Dpad::D => Some(Button::Down),
+        Dpad::L => Some(Button::Left),
+        Dpad::R => Some(Button::Right),
+        Dpad::UL => Some(Button::UpLeft),
+        Dpad::UR => Some(Button::UpRight),
+        Dpad::DL => Some(Button::DownLeft),
+        Dpad::DR => Some(Button::DownRight),
+        Dpad::Off => None,
+    };
+
+    BUTTONS.iter()
+        .filter(|&&button| held & report_bit(button) != 0 || position == Some(button))
+        .fold(0, |bits, &button| bits | 1 << button as usize)
+}
+
+// Matches the steps backwards from the newest press, which has to be from
+// this report and finish the last step. Each earlier step takes the latest
+// press before that fits it, which keeps the time from the first step as
+// short as it can be.
+fn sequence_completed(sequence: &Sequence, log: &VecDeque<(Button, u32, Instant)>, now: Instant) -> bool {
+    let fits = |step: &[Button], &(button, held, _): &(Button, u32, Instant)| {
+        step.contains(&button) && step.iter().all(|&b| held & 1 << b as usize != 0)
+    };
+
+    let mut i = log.len();
+    let mut first = now;
+    for (n, step) in sequence.steps.iter().rev().enumerate() {
+        loop {
+            if i == 0 { return false; }
+            i -= 1;
+            if n == 0 && log[i].2 != now { return false; }
+            if fits(step, &log[i]) { break; }
+        }
+        first = log[i].2;
+    }
+
+    now.saturating_duration_since(first) <= sequence.window
+}
+
+fn action_index(action: Action) -> usize {
+    match action {
+        Action::Key(key) => key as usize,
+        Action::Mouse(button) => Key::COUNT as usize + button as usize,
+        // never also a plain Key, parse_action only makes these Media
+        Action::Media(key) => key as usize,
+    }
+}
+
+// What sent a key or mouse button event, for the event log
+#[derive(Debug, Copy, Clone)]
+enum Cause {
+    Button(Button),
+    // index into Controller::chords
+    Chord(usize),
+    // index into the active profile's stick chords
+    StickChord(usize),
+    // a stick in WASD mode, 0 for the left one and 1 the right
+    Stick(usize),
+    // a bit of the report's last byte
+    Unused(usize),
+    // index into the active profile's sequences
+    Sequence(usize),
+    // the panic buttons letting go of what nothing else would
+    Panic,
+    // release-all sent to the --control socket
+    Control,
+}
+
+impl fmt::Display for Cause {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Cause::Button(button) => write!(f, "{:?}", button),
+            Cause::Chord(i) => write!(f, "chord {}", i),
+            Cause::StickChord(i) => write!(f, "stick chord {}", i),
+            Cause::Stick(0) => write!(f, "left stick"),
+            Cause::Stick(_) => write!(f, "right stick"),
+            Cause::Unused(bit) => write!(f, "unused bit {}", bit),
+            Cause::Sequence(i) => write!(f, "sequence {}", i),
+            Cause::Panic => write!(f, "panic buttons"),
+            Cause::Control => write!(f, "control socket"),
+        }
+    }
+}
+
+// Every key and mouse button event goes through these two, so they're
+// where the event log is written
+fn action_down<S: OutputSink>(action: Action, cause: Cause, read_at: Instant, sink: &mut S) -> Result<(), tfc::Error> {
+    let direction = match action {
+        Action::Key(key) => { sink.key_down(key)?; "down" },
+        Action::Mouse(button) => { sink.mouse_down(button)?; "down" },
+        Action::Media(key) => {
+            sink.key_down(key)?;
+            sink.key_up(key)?;
+            "tap"
+        },
+    };
+
+    log_event(action, direction, cause, read_at);
+    Ok(())
+}
+
+fn action_up<S: OutputSink>(action: Action, cause: Cause, read_at: Instant, sink: &mut S) -> Result<(), tfc::Error> {
+    match action {
+        Action::Key(key) => sink.key_up(key)?,
+        Action::Mouse(button) => sink.mouse_up(button)?,
+        // already let go on the way down
+        Action::Media(_) => return Ok(()),
+    }
+
+    log_event(action, "up", cause, read_at);
+    Ok(())
+}
+
+// Types a text binding once. Resyncs and later polls never retype it, so
+// a failure part way is only logged rather than retried or passed up to
+// have the rest of the input resynced.
+fn type_text<S: OutputSink>(text: &str, button: Button, sink: &mut S) {
+    match sink.type_text(text) {
+        Ok(()) => debug!("[{:>10.3}ms] typed {:?} from {:?}", millis(uptime()), text, button),
+        Err(e) => warn!("Could not type {:?} for {:?}: {:?}", text, button, e),
+    }
+}
+
+// Debug level, with a monotonic timestamp and how long after its report
+// was read the event went out
+fn log_event(action: Action, direction: &str, cause: Cause, read_at: Instant) {
+    debug!("[{:>10.3}ms] {} {} from {}, {:.3}ms after read",
+        millis(uptime()), action, direction, cause, millis(read_at.elapsed()));
+}
+
+// Time since startup, unlike the log's own timestamps this can't jump
+pub fn uptime() -> Duration {
+    static START: OnceLock<Instant> = OnceLock::new();
+    START.get_or_init(Instant::now).elapsed()
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+// Reads every controller matching target and sends what they do to sink,
+// until stop is called or the devices can no longer be read
+pub fn run<S: OutputSink>(config: Config, target: DeviceMatch, options: &PollOptions, sink: &mut S) -> Result<(), Error> {
+    run_with(Source::Device { target, recorder: None }, config, options, &mut None, &Watchers::default(), sink)
+}
+
+// What dispatch checks on between reports, besides the controllers. Each
+// is only there if it was asked for.
+#[derive(Default)]
+pub struct Watchers {
+    // the focused app, for profiles and focus_apps
+    pub apps: Option<AppWatcher>,
+    // the config file, for --watch
+    pub config: Option<ConfigWatcher>,
+    pub control: Option<Control>,
+}
+
+// The devices are read on their own thread so slow event sends can't hold
+// up the next read. hidapi handles can't be moved between threads and only
+// one api can exist at a time, so that one thread owns the api and every
+// device, and does all the opening and reconnecting itself.
+pub fn run_with<S: OutputSink>(source: Source, config: Config, options: &PollOptions, overlay: &mut Option<Overlay>, watchers: &Watchers, sink: &mut S) -> Result<(), Error> {
+    let (tx, rx) = channel::bounded(QUEUE_SIZE, droppable);
+    let reader = spawn_reader(source, options, tx);
+    dispatch(rx, sink, config, options, overlay, watchers);
+    join_reader(reader)
+}
+
+// Like run_with, but passes the pad on as a gamepad instead of mapping it,
+// with only pad_buttons and the calibration from the config. There's one
+// virtual pad, so only the first controller is passed on. A quiet pad
+// keeps its last state, and it's let go of when it's lost or on shutdown.
+pub fn run_pad<P: PadSink>(source: Source, config: &Config, options: &PollOptions, pad: &mut P) -> Result<(), Error> {
+    let (tx, rx) = channel::bounded(QUEUE_SIZE, droppable);
+    let reader = spawn_reader(source, options, tx);
+    let at_rest = PadState::new(&Input::at_rest(&config.calibration), &config.calibration, &config.pad_buttons);
+    let mut warned = false;
+
+    while running() {
+        let state = match rx.recv_timeout(options.read_timeout) {
+            Ok((0, Report::Input(input))) => PadState::new(&input, &config.calibration, &config.pad_buttons),
+            Ok((0, Report::NoInput)) | Ok((0, Report::Disconnected)) => at_rest.clone(),
+            Ok((0, Report::Connected(_))) | Err(channel::RecvError::Timeout) => continue,
+            Ok((slot, _)) => {
+                if !warned { warn!("Only the first controller is passed on, device {} is ignored", slot); }
+                warned = true;
+                continue;
+            },
+            Err(channel::RecvError::Closed) => break,
+        };
+
+        if let Err(e) = pad.send(&state) { debug!("Could not send pad state: {:?}", e); }
+    }
+
+    info!("Releasing the virtual pad...");
+    if let Err(e) = pad.send(&at_rest) { debug!("Could not send pad state: {:?}", e); }
+    join_reader(reader)
+}
+
+fn spawn_reader(source: Source, options: &PollOptions, tx: channel::Sender<(usize, Report)>) -> thread::JoinHandle<Result<(), Error>> {
+    let options = options.clone();
+
+    thread::spawn(move || match source {
+        Source::Device { target, mut recorder } => read_devices(&target, &options, &mut recorder, tx),
+        Source::Replay(replay) => {
+            read_replay(replay, &options, tx);
+            Ok(())
+        },
+    })
+}
+
+fn join_reader(reader: thread::JoinHandle<Result<(), Error>>) -> Result<(), Error> {
+    reader.join().unwrap_or_else(|_| {
+        error!("Reader thread panicked");
+        Ok(())
+    })
+}
+
+// An opened controller, known by its serial number or failing that its
+// path, so it's reopened into the same slot and profile after a disconnect
+struct Slot {
+    id: String,
+    // None while it's unplugged
+    device: Option<hidapi::HidDevice>,
+    // consecutive failed reads
+    errors: u32,
+    // when it last sent a report, or was opened, for the watchdog
+    last_report: Instant,
+    // reconnect attempts since it was lost
+    attempts: u32,
+    // of those, the ones that found the device but couldn't open it
+    open_failures: u32,
+    // current backoff, and when the next reconnect attempt is due
+    delay: Duration,
+    retry_at: Instant,
+}
+
+// Opens every matching device, each index in the Vec being the slot its
+// reports are sent with
+fn read_devices(target: &DeviceMatch, options: &PollOptions, recorder: &mut Option<Recorder>, tx: channel::Sender<(usize, Report)>) -> Result<(), Error> {
+    let mut api = hidapi::HidApi::new()?;
+    let mut slots: Vec<Slot> = open_targets(&api, target, &options.interface)?.into_iter()
+        .map(|(id, device)| Slot { id, device: Some(device), errors: 0, last_report: Instant::now(), attempts: 0, open_failures: 0, delay: options.reconnect_min, retry_at: Instant::now() })
+        .collect();
+
+    // a recording has no room to say which pad a report came from
+    if slots.len() > 1 && recorder.is_some() { warn!("Only the first controller will be recorded"); }
+
+    for (i, slot) in slots.iter().enumerate() { tx.send((i, Report::Connected(slot.id.clone()))); }
+
+    poll(&mut api, &mut slots, options, recorder, &tx)
+}
+
+// Sends the recorded reports at the same pace they were recorded. A corrupt
+// line ends the replay there, same as the device going away.
+fn read_replay(replay: Replay, options: &PollOptions, tx: channel::Sender<(usize, Report)>) {
+    info!("Replaying recording...");
+    let start = Instant::now();
+
+    for entry in replay {
+        if !running() { return; }
+
+        let (at, data) = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                error!("Stopped replay: {}", e);
+                return;
+            }
+        };
+
+        if let Some(wait) = at.checked_sub(start.elapsed()) { thread::sleep(wait); }
+
+        match layout::decode(options.layout.decoder(), &data, options.report_id) {
+            Ok(input) => { tx.send((0, Report::Input(input))); },
+            Err(e) => warn!("Ignoring report {:02x?}: {}", data, e),
+        }
+    }
+
+    info!("Replay finished");
+}
+
+// Averages the sticks over a couple of seconds at rest. Reports with
+// anything pressed are skipped, in case the sticks are being moved too.
+pub fn calibrate(target: &DeviceMatch, options: &PollOptions) -> Result<Calibration, Error> {
+    let api = hidapi::HidApi::new()?;
+    let device = open_first(&api, target, &options.interface)?;
+
+    info!("Calibrating, leave the sticks centered and don't press anything...");
+    let start = Instant::now();
+    let mut sums = [0u32; 4];
+    let mut samples: u32 = 0;
+
+    while start.elapsed() < CALIBRATION_TIME {
+        let input = match read_input(&device, options.read_timeout, options, &mut None)? {
+            Some(input) => input,
+            None => continue,
+        };
+
+        // raw dpad values past 7 are the hat at rest
+        if input.buttons != 0 || input.extra != 0 || input.dpad <= 7 { continue; }
+
+        for (sum, axis) in sums.iter_mut().zip(&[input.lstick1, input.lstick2, input.rstick1, input.rstick2]) {
+            *sum += *axis as u32;
+        }
+        samples += 1;
+    }
+
+    if samples < MIN_CALIBRATION_SAMPLES {
+        return Err(Error::Calibration(format!("only {} usable reports, keep the controller still and try again", samples)));
+    }
+
+    let center = |sum: u32| ((sum + samples / 2) / samples) as u8;
+    let calibration = Calibration {
+        lstick: [center(sums[0]), center(sums[1])],
+        rstick: [center(sums[2]), center(sums[3])],
+    };
+    info!("Left stick center {:?}, right stick center {:?}", calibration.lstick, calibration.rstick);
+
+    Ok(calibration)
+}
+
+// Asks for a button on the first controller and then the key for it,
+// over and over, until "done" is typed, no button is pressed within
+// REBIND_TIMEOUT or Ctrl-C. Prompts go to stderr, leaving stdout for the
+// table in case it's printed there. Rebinding a button replaces what it
+// was given before.
+pub fn rebind(target: &DeviceMatch, options: &PollOptions) -> Result<ButtonTable, Error> {
+    let api = hidapi::HidApi::new()?;
+    let device = open_first(&api, target, &options.interface)?;
+    let mut table = ButtonTable::default();
+
+    while running() {
+        eprintln!("Press the button you want to bind, then type the key for it");
+        let button = match wait_for_button(&device, options)? {
+            Some(button) => button,
+            None => break,
+        };
+
+        let action = match read_action(button)? {
+            Reply::Action(action) => action,
+            Reply::Skip => continue,
+            Reply::Done => break,
+        };
+        eprintln!("{:?} = {}", button, action);
+
+        table.bindings.retain(|&(bound, _)| bound != button);
+        table.bindings.push((button, action));
+
+        // whatever was pressed while typing is stale by now
+        while read_input(&device, Duration::ZERO, options, &mut None)?.is_some() {}
+    }
+
+    if !running() { eprintln!(); }
+    if table.bindings.is_empty() { info!("Nothing was bound"); }
+    Ok(table)
+}
+
+// The one button pressed and let go, or None once REBIND_TIMEOUT is up
+// without one. Anything already held has to be let go first. A press that
+// took more than one button at any point, rolling round the dpad included,
+// is asked for again rather than guessed at.
+fn wait_for_button(device: &hidapi::HidDevice, options: &PollOptions) -> Result<Option<Button>, Error> {
+    let start = Instant::now();
+    // None until nothing's held, then every button held since
+    let mut pressed: Option<u32> = None;
+
+    while running() {
+        let mid_press = matches!(pressed, Some(bits) if bits != 0);
+        if !mid_press && start.elapsed() >= REBIND_TIMEOUT {
+            info!("No button pressed for {}s, done", REBIND_TIMEOUT.as_secs());
+            return Ok(None);
+        }
+
+        let input = match read_input(device, options.read_timeout, options, &mut None)? {
+            Some(input) => input,
+            None => continue,
+        };
+        let held = sequence_bits(input.buttons, input.extra, input.dpad);
+
+        pressed = match pressed {
+            None if held == 0 => Some(0),
+            None => None,
+            Some(bits) if held != 0 => Some(bits | held),
+            Some(0) => Some(0),
+            Some(bits) if bits.count_ones() == 1 => return Ok(Some(BUTTONS[bits.trailing_zeros() as usize])),
+            Some(_) => {
+                eprintln!("That was more than one button, press just the one");
+                Some(0)
+            },
+        };
+    }
+
+    Ok(None)
+}
+
+enum Reply {
+    Action(Action),
+    Skip,
+    Done,
+}
+
+// A line from the terminal naming the action for button, asking again if
+// it isn't one. The end of stdin is taken as done.
+fn read_action(button: Button) -> Result<Reply, Error> {
+    let stdin = io::stdin();
+
+    loop {
+        eprint!("Key for {:?} (empty to skip, \"done\" to finish): ", button);
+        let _ = io::stderr().flush();
+
+        let mut line = String::new();
+        let read = stdin.read_line(&mut line).map_err(|error| Error::Io { context: "Could not read the key".to_string(), error })?;
+        if read == 0 || !running() { return Ok(Reply::Done); }
+
+        match line.trim() {
+            "" => return Ok(Reply::Skip),
+            name if name.eq_ignore_ascii_case("done") => return Ok(Reply::Done),
+            name => match config::parse_action(name) {
+                Some(action) => return Ok(Reply::Action(action)),
+                None => eprintln!("Unknown key {:?}, key names are the ones the config takes", name),
+            },
+        }
+    }
+}
+
+// Redraws one line with what the first controller reports, until TEST_TIME
+// is up or Ctrl-C. Nothing is sent, so it's safe to mash buttons.
+pub fn self_test(target: &DeviceMatch, options: &PollOptions) -> Result<(), Error> {
+    let api = hidapi::HidApi::new()?;
+    let device = open_first(&api, target, &options.interface)?;
+
+    info!("Showing input for {}s, Ctrl-C to stop sooner", TEST_TIME.as_secs());
+    let start = Instant::now();
+    let mut stdout = io::stdout();
+    let mut reports: u32 = 0;
+
+    while running() && start.elapsed() < TEST_TIME {
+        let input = match read_input(&device, options.read_timeout, options, &mut None)? {
+            Some(input) => input,
+            None => continue,
+        };
+        reports += 1;
+
+        // \x1b[K clears whatever's left of a longer previous line
+        let _ = write!(stdout, "\r{:<60} raw {:02x} {:02x} {:02x}\x1b[K", input, input.buttons, input.extra, input.dpad);
+        let _ = stdout.flush();
+    }
+
+    println!();
+    info!("Read {} reports", reports);
+    Ok(())
+}
+
+pub fn list_devices(api: &hidapi::HidApi) {
+    println!("{:<32} {:<24} {:<6} {:<6} {:<10} {:<6} {:<9} SERIAL", "PRODUCT", "MANUFACTURER", "VID", "PID", "USAGE PAGE", "USAGE", "INTERFACE");
+
+    for device_info in api.devices() {
+        println!("{:<32} {:<24} {:<6} {:<6} {:<10} {:<6} {:<9} {}",
+            device_info.product_string.as_deref().unwrap_or("<none>"),
+            device_info.manufacturer_string.as_deref().unwrap_or("<none>"),
+            format!("{:04x}", device_info.vendor_id),
+            format!("{:04x}", device_info.product_id),
+            format!("{:04x}", device_info.usage_page),
+            format!("{:04x}", device_info.usage),
+            device_info.interface_number,
+            device_info.serial_number.as_deref().filter(|serial| !serial.is_empty()).unwrap_or("<none>"));
+    }
+}
+
+// Asks run to let go of everything and return, from any thread
+pub fn stop() {
+    RUNNING.store(false, Ordering::SeqCst);
+}
+
+fn running() -> bool {
+    RUNNING.load(Ordering::SeqCst)
+}
+
+// Tries once to reopen each lost device that's due, by the id it was
+// first opened with. A failed attempt doubles that device's backoff. Only
+// a device that's there but keeps failing to open is an error.
+fn reconnect(api: &mut hidapi::HidApi, slots: &mut [Slot], options: &PollOptions, tx: &channel::Sender<(usize, Report)>) -> Result<(), Error> {
+    let now = Instant::now();
+    let due = |slot: &Slot| slot.device.is_none() && slot.retry_at <= now;
+    if !slots.iter().any(due) { return Ok(()); }
+
+    if let Err(e) = api.refresh_devices() {
+        error!("Could not refresh devices: {:?}", e);
+        return Ok(());
+    }
+
+    for (i, slot) in slots.iter_mut().enumerate().filter(|(_, slot)| due(slot)) {
+        slot.attempts += 1;
+        info!("Reconnecting to device {} (attempt {}, after {}ms)...", i, slot.attempts, slot.delay.as_millis());
+
+        let found = by_id(api.devices().iter().filter(|device_info| device_id(device_info) == slot.id && options.interface.matches(device_info)));
+        let opened = match found.first() {
+            Some(device_info) => match open_device(api, device_info) {
+                Ok(device) => Some(device),
+                Err(e) => {
+                    slot.open_failures += 1;
+                    if slot.open_failures >= MAX_OPEN_FAILURES {
+                        error!("Giving up on device {} after failing to open it {} times", i, slot.open_failures);
+                        return Err(e);
+                    }
+                    error!("{}", e);
+                    None
+                },
+            },
+            // not plugged back in yet
+            None => None,
+        };
+
+        match opened {
+            Some(device) => {
+                slot.device = Some(device);
+                slot.errors = 0;
+                slot.last_report = Instant::now();
+                slot.attempts = 0;
+                slot.open_failures = 0;
+                slot.delay = options.reconnect_min;
+                tx.send((i, Report::Connected(slot.id.clone())));
+            },
+            None => {
+                slot.delay = (slot.delay * 2).min(options.reconnect_max);
+                slot.retry_at = Instant::now() + jitter(slot.delay);
+            },
+        }
+    }
+
+    Ok(())
+}
+
+// The delay plus up to RECONNECT_JITTER of it again. std has no random
+// numbers, but every RandomState is seeded differently.
+fn jitter(delay: Duration) -> Duration {
+    let random = RandomState::new().build_hasher().finish();
+    let fraction = (random % 1000) as f64 / 1000.0 * RECONNECT_JITTER;
+    delay + delay.mul_f64(fraction)
+}
+
+// Reads each open device in turn until we're shutting down. With more than
+// one, reads don't wait for a report so a quiet pad can't hold up the rest.
+// A device is given up on after max_read_errors failed reads in a row, or
+// once it's been quiet for longer than the watchdog allows, and lost ones
+// are looked for again with a backoff, see reconnect. Returns
+// once we're shutting down, or with the error if reconnecting gives up.
+fn poll(api: &mut hidapi::HidApi, slots: &mut [Slot], options: &PollOptions, recorder: &mut Option<Recorder>, tx: &channel::Sender<(usize, Report)>) -> Result<(), Error> {
+    info!("Polling {} device(s)...", slots.len());
+    let timeout = if slots.len() > 1 { Duration::ZERO } else { options.read_timeout };
+    let mut no_recorder = None;
+
+    while running() {
+        for (i, slot) in slots.iter_mut().enumerate() {
+            let device = match &slot.device {
+                Some(device) => device,
+                None => continue,
+            };
+            let recorder = if i == 0 { &mut *recorder } else { &mut no_recorder };
+
+            let report = match read_input(device, timeout, options, recorder) {
+                Ok(Some(input)) => {
+                    slot.errors = 0;
+                    slot.last_report = Instant::now();
+                    Report::Input(input)
+                },
+                // dispatch notices the gap itself, unless it's gone on so
+                // long the pad has probably stopped sending without saying
+                Ok(None) => {
+                    slot.errors = 0;
+                    match options.watchdog {
+                        Some(watchdog) if slot.last_report.elapsed() >= watchdog => {
+                            warn!("No reports from device {} for {}ms, reopening it", i, watchdog.as_millis());
+                            slot.device = None;
+                            slot.delay = options.reconnect_min;
+                            slot.retry_at = Instant::now();
+                            Report::Disconnected
+                        },
+                        _ => continue,
+                    }
+                },
+                Err(e) => {
+                    slot.errors += 1;
+                    if slot.errors < options.max_read_errors { Report::NoInput }
+                    else {
+                        error!("Lost connection to device {}: {}", i, e);
+                        slot.device = None;
+                        slot.delay = options.reconnect_min;
+                        slot.retry_at = Instant::now() + jitter(slot.delay);
+                        Report::Disconnected
+                    }
+                }
+            };
+
+            if !tx.send((i, report)) { debug!("Dispatch fell behind, dropped a report"); }
+        }
+
+        reconnect(api, slots, options, tx)?;
+
+        // nothing to read, so just wait for the next reconnect, waking
+        // now and then to notice a shutdown
+        if slots.iter().all(|slot| slot.device.is_none()) {
+            let next = slots.iter().map(|slot| slot.retry_at).min().unwrap_or_else(Instant::now);
+            thread::sleep(next.saturating_duration_since(Instant::now()).min(SHUTDOWN_CHECK));
+        } else if !options.interval.is_zero() { thread::sleep(options.interval); }
+    }
+
+    Ok(())
+}
+
+// One device's share of dispatch
+struct Pad {
+    // None for a replay
+    id: Option<String>,
+    controller: Controller,
+    // reused while the device is quiet
+    last: Input,
+}
+
+impl Pad {
+    fn new(config: &Config, id: Option<String>) -> Pad {
+        let profile = id.as_ref().map_or(0, |id| config.profile_for_device(id));
+        Pad { id, controller: Controller::new(config, profile), last: Input::at_rest(&config.calibration) }
+    }
+}
+
+// Runs until the reader thread finishes or we're shutting down. Each device
+// gets its own Controller when it connects, so each keeps its own profile
+// and held keys, and all of them send through the one sink. A reloaded
+// config is only swapped in here, between reports, so no controller ever
+// sees half of one.
+fn dispatch<S: OutputSink>(rx: channel::Receiver<(usize, Report)>, sink: &mut S, mut config: Config, options: &PollOptions, overlay: &mut Option<Overlay>, watchers: &Watchers) {
+    let apps = &watchers.apps;
+    // indexed by the reader's device slot
+    let mut pads: Vec<Option<Pad>> = Vec::new();
+    let mut i: u32 = 0;
+    let mut send_failed = false;
+    // focused app, kept so a fresh controller after a reconnect can pick
+    // the same profile
+    let mut app: Option<String> = None;
+    let mut latency = options.latency.map(Latency::new);
+    // only the first device's, like the overlay
+    let mut drops = options.drops.map(Drops::new);
+    // set while an app outside focus_apps is focused, nothing is sent then
+    let mut unfocused = false;
+    // reports read from any device, for max_reports
+    let mut reports: u64 = 0;
+    // when the sticks are next due to move the cursor, with mouse_rate
+    let mut next_tick: Option<Instant> = None;
+
+    while running() {
+        if let Some(reloaded) = watchers.config.as_ref().and_then(|watcher| watcher.changed()) {
+            config = reloaded;
+            swap_config(&mut pads, &config, app.as_deref(), sink);
+        }
+
+        while let Some(Request { command, reply }) = watchers.control.as_ref().and_then(Control::pending) {
+            let result = match command {
+                Command::Reload(reloaded) => {
+                    config = *reloaded;
+                    swap_config(&mut pads, &config, app.as_deref(), sink);
+                    Ok(String::new())
+                },
+                Command::Profile(name) => switch_profiles(&name, &mut pads, &config, sink),
+                Command::State => Ok(describe_pads(&pads)),
+                Command::ReleaseAll => release_everything(&mut pads, sink),
+            };
+            reply.send(result);
+        }
+
+        if let Some(changed) = apps.as_ref().and_then(|apps| apps.changed()) {
+            app = changed;
+            for pad in pads.iter_mut().flatten() {
+                if let Err(e) = pad.controller.select_app(app.as_deref(), sink) { error!("Could not release keys: {:?}", e); }
+            }
+        }
+
+        // without a watcher there's no telling, so keep sending
+        let focused = apps.is_none() || config.sends_to(app.as_deref());
+        if focused == unfocused {
+            unfocused = !focused;
+
+            if unfocused {
+                info!("Focus left {:?}, letting go of every key", config.focus_apps);
+                for pad in pads.iter_mut().flatten() { release_pad(pad, sink); }
+            } else {
+                // press whatever is still held
+                info!("Focus is back, sending input again");
+                for pad in pads.iter_mut().flatten() {
+                    let input = Input { read_at: Instant::now(), ..pad.last };
+                    if let Err(e) = pad.controller.update(input, sink) { debug!("Could not send input: {:?}", e); }
+                }
+            }
+        }
+
+        // resync every so often in case a send failed part way
+        if options.clear_every != 0 {
+            if i == 0 { pads.iter_mut().flatten().for_each(|pad| pad.controller.clear_state()); }
+            i = (i + 1) % options.clear_every;
+        }
+
+        // woken early for the next tick, if it's sooner
+        let period = config.mouse_rate.map(|rate| Duration::from_secs_f32(1.0 / rate));
+        let timeout = match (period, next_tick) {
+            (Some(_), Some(at)) => at.saturating_duration_since(Instant::now()).min(options.read_timeout),
+            _ => options.read_timeout,
+        };
+
+        let received = match rx.recv_timeout(timeout) {
+            Ok((slot, Report::Connected(id))) => {
+                if slot == 0 { drops.iter_mut().for_each(Drops::reset); }
+                let mut pad = Pad::new(&config, Some(id.clone()));
+                info!("Device {} ({}) uses profile {:?}", slot, id, config.profiles[pad.controller.active].name);
+
+                if let Err(e) = pad.controller.select_app(app.as_deref(), sink) { error!("Could not release keys: {:?}", e); }
+                if let Some(mut old) = pad_slot(&mut pads, slot).replace(pad) { release_pad(&mut old, sink); }
+                continue;
+            },
+            Ok((slot, Report::Disconnected)) => {
+                if let Some(mut old) = pad_slot(&mut pads, slot).take() { release_pad(&mut old, sink); }
+                continue;
+            },
+            Ok((slot, Report::Input(input))) => {
+                reports += 1;
+                if slot == 0 { drops.iter_mut().for_each(|drops| drops.record(input.unused)); }
+                Some((slot, input))
+            },
+            // assume no input
+            Ok((slot, Report::NoInput)) => Some((slot, Input::at_rest(&config.calibration))),
+            Err(channel::RecvError::Timeout) => None,
+            Err(channel::RecvError::Closed) => break,
+        };
+
+        // a replay never says it's connected
+        if let Some((slot, _)) = received {
+            pad_slot(&mut pads, slot).get_or_insert_with(|| Pad::new(&config, None));
+        }
+
+        for (slot, pad) in pads.iter_mut().enumerate() {
+            let pad = match pad {
+                Some(pad) => pad,
+                None => continue,
+            };
+
+            let (input, fresh) = match received {
+                Some((from, input)) if from == slot => (input, true),
+                // nothing from this device in a while, some pads only send a
+                // report when something changes so keep holding whatever was
+                // last seen. Any events from it are down to timing, not the
+                // old report.
+                _ if pad.last.read_at.elapsed() >= options.read_timeout => (Input { read_at: Instant::now(), ..pad.last }, false),
+                _ => continue,
+            };
+            pad.last = input;
+
+            // the overlay only follows the first device
+            if slot == 0 {
+                if let Some(overlay) = overlay { overlay.publish(pad.controller.snapshot(&input)); }
+            }
+
+            if unfocused { continue; }
+
+            let result = match &mut latency {
+                // a held over input wasn't read just now, so has no latency to speak of
+                Some(latency) if fresh => {
+                    let mut timed = TimedSink::new(sink);
+                    let result = pad.controller.update(input, &mut timed);
+                    if let Some(sent) = timed.last_event { latency.record(sent.duration_since(input.read_at)); }
+                    result
+                },
+                _ => pad.controller.update(input, sink),
+            };
+
+            if let Err(e) = result {
+                // only explain the first failure, otherwise this repeats every poll
+                if !send_failed { report_send_error(&e); }
+                else { debug!("Could not send input: {:?}", e); }
+
+                send_failed = true;
+                pad.controller.clear_state();
+            }
+
+            // stops the reader too
+            if pad.controller.quit_requested() {
+                info!("Home held, quitting");
+                RUNNING.store(false, Ordering::SeqCst);
+            }
+        }
+
+        if let Some(period) = period {
+            let now = Instant::now();
+            let due = next_tick.get_or_insert(now);
+            if now >= *due {
+                if !unfocused {
+                    for pad in pads.iter_mut().flatten() {
+                        if let Err(e) = pad.controller.tick(sink) { debug!("Could not send input: {:?}", e); }
+                    }
+                }
+
+                // a late tick isn't made up for, that would only jump the cursor
+                *due += period;
+                if *due <= now { *due = now + period; }
+            }
+        }
+
+        if let Some(latency) = &mut latency { latency.report(); }
+        if let Some(drops) = &mut drops { drops.report(); }
+
+        if options.max_reports.is_some_and(|max| reports >= max) {
+            info!("Handled {} reports, quitting", reports);
+            RUNNING.store(false, Ordering::SeqCst);
+        }
+    }
+
+    info!("Releasing held keys...");
+    for pad in pads.iter_mut().flatten() { release_pad(pad, sink); }
+}
+
+// A reloaded config, swapped in for every connected pad. Keys held under
+// the old mapping might not be released by the new one, so they're let go
+// first.
+fn swap_config<S: OutputSink>(pads: &mut [Option<Pad>], config: &Config, app: Option<&str>, sink: &mut S) {
+    for pad in pads.iter_mut() {
+        if let Some(mut old) = pad.take() {
+            release_pad(&mut old, sink);
+
+            let mut new = Pad { last: old.last, ..Pad::new(config, old.id) };
+            // slow mode stays on, as long as there's still a button to turn it off
+            if new.controller.slow_bit != 0 {
+                new.controller.slow = old.controller.slow;
+                new.controller.slow_held = old.controller.slow_held;
+            }
+            if let Err(e) = new.controller.select_app(app, sink) { error!("Could not release keys: {:?}", e); }
+            *pad = Some(new);
+        }
+    }
+}
+
+// --control's profile command, by name or counting from 1
+fn switch_profiles<S: OutputSink>(name: &str, pads: &mut [Option<Pad>], config: &Config, sink: &mut S) -> Result<String, String> {
+    let index = config.profiles.iter().position(|profile| profile.name == name)
+        .or_else(|| name.parse::<usize>().ok().filter(|&n| n >= 1 && n <= config.profiles.len()).map(|n| n - 1))
+        .ok_or_else(|| format!("there's no profile {:?}", name))?;
+    if pads.iter().all(Option::is_none) { return Err("no controller is connected".to_string()); }
+
+    for pad in pads.iter_mut().flatten() {
+        pad.controller.select_profile(index, sink).map_err(|e| format!("could not release keys: {:?}", e))?;
+    }
+    Ok(String::new())
+}
+
+// --control's state, a line for each connected pad
+fn describe_pads(pads: &[Option<Pad>]) -> String {
+    pads.iter()
+        .enumerate()
+        .filter_map(|(slot, pad)| pad.as_ref().map(|pad| (slot, pad)))
+        .map(|(slot, pad)| {
+            let controller = &pad.controller;
+            let slow = if controller.slow { " slow" } else { "" };
+            format!("device {} {} profile {:?}{} input {}\n", slot, pad.id.as_deref().unwrap_or("replay"),
+                controller.profiles[controller.active].name, slow, pad.last)
+        })
+        .collect()
+}
+
+// --control's release-all, the panic buttons for every pad at once
+fn release_everything<S: OutputSink>(pads: &mut [Option<Pad>], sink: &mut S) -> Result<String, String> {
+    if pads.iter().all(Option::is_none) { return Err("no controller is connected".to_string()); }
+
+    warn!("Letting go of everything, as asked over the control socket");
+    for pad in pads.iter_mut().flatten() {
+        let last = pad.last;
+        pad.controller.release_everything(&last, sink).map_err(|e| format!("could not release keys: {:?}", e))?;
+    }
+    Ok(String::new())
+}
+
+fn pad_slot(pads: &mut Vec<Option<Pad>>, slot: usize) -> &mut Option<Pad> {
+    if pads.len() <= slot { pads.resize_with(slot + 1, || None); }
+    &mut pads[slot]
+}
+
+fn release_pad<S: OutputSink>(pad: &mut Pad, sink: &mut S) {
+    if let Err(e) = pad.controller.release_all(sink) { error!("Could not release keys: {:?}", e); }
+}
+
+fn report_send_error(e: &tfc::Error) {
+    error!("Could not send input: {:?}", e);
+
+    if !permissions::accessibility_granted() {
+        error!("Keys can't be sent without Accessibility permission. Grant it to this program (or the terminal \
+                running it) under System Settings > Privacy & Security > Accessibility, then restart it.");
+    }
+}
+
+// What read_input reads from, a HidDevice everywhere but the tests
+pub trait ReadReport {
+    // hidapi's read_timeout: how many bytes of buf were filled, 0 if nothing
+    // came in time
+    fn read_timeout(&self, buf: &mut [u8], timeout: i32) -> hidapi::HidResult<usize>;
+}
+
+impl ReadReport for hidapi::HidDevice {
+    fn read_timeout(&self, buf: &mut [u8], timeout: i32) -> hidapi::HidResult<usize> {
+        hidapi::HidDevice::read_timeout(self, buf, timeout)
+    }
+}
+
+// Ok(None) if no full report arrived within the read timeout. Every report
+// read, valid or not, goes to the recorder if there is one.
+pub fn read_input<D: ReadReport>(device: &D, timeout: Duration, options: &PollOptions, recorder: &mut Option<Recorder>) -> Result<Option<Input>, Error> {
+    // Read data from device, with room for whatever it sends so an overlong
+    // report is caught rather than cut short
+    let mut buf = [0u8; MAX_REPORT_LEN];
+    let timeout = i32::try_from(timeout.as_millis()).unwrap_or(i32::MAX);
+
+    let read = device.read_timeout(&mut buf[..], timeout)?;
+    if read == 0 { return Ok(None); }
+
+    if let Some(r) = recorder {
+        if let Err(e) = r.record(&buf[..read]) {
+            error!("Stopped recording: {}", e);
+            *recorder = None;
+        }
+    }
+
+    let input = match layout::decode(options.layout.decoder(), &buf[..read], options.report_id) {
+        Ok(input) => input,
+        Err(e) => {
+            warn!("Ignoring report {:02x?}: {}", &buf[..read], e);
+            return Ok(None);
+        }
+    };
+    debug!("Read: {}", input);
+
+    Ok(Some(input))
+}
+
+// Every device matching target, with the id it's known by for the session.
+// They're in path order so the same pads get the same slots each run,
+// whatever order the OS lists them in.
+pub fn open_targets(api: &hidapi::HidApi, target: &DeviceMatch, interface: &InterfaceMatch) -> Result<Vec<(String, hidapi::HidDevice)>, Error> {
+    let found = find_targets(api.devices(), target, interface);
+    if found.is_empty() { return Err(Error::DeviceNotFound(target.clone())); }
+
+    found.into_iter()
+        .map(|device_info| Ok((device_id(device_info), open_device(api, device_info)?)))
+        .collect()
+}
+
+// For the commands that only look at one pad, the first in path order.
+// The others are only listed, so it's clear which one is being used.
+fn open_first(api: &hidapi::HidApi, target: &DeviceMatch, interface: &InterfaceMatch) -> Result<hidapi::HidDevice, Error> {
+    let found = find_targets(api.devices(), target, interface);
+
+    if found.len() > 1 {
+        let ids: Vec<String> = found.iter().map(|device_info| device_ids(device_info)).collect();
+        warn!("{} devices match {}: {}. Using the first, --serial picks another", found.len(), target, ids.join(", "));
+    }
+
+    match found.first() {
+        Some(device_info) => open_device(api, device_info),
+        None => Err(Error::DeviceNotFound(target.clone())),
+    }
+}
+
+// Devices filtered out by the interface match are left out entirely, so a
+// pad with none of the wanted interface counts as not found
+fn find_targets<'a>(devices: &'a [hidapi::HidDeviceInfo], target: &DeviceMatch, interface: &InterfaceMatch) -> Vec<&'a hidapi::HidDeviceInfo> {
+    let mut matched: Vec<&hidapi::HidDeviceInfo> = Vec::new();
+
+    for device_info in devices {
+        let is_match = match target {
+            DeviceMatch::ByName(name) => match &device_info.product_string {
+                Some(product) => product == name,
+                // plenty of devices have no product string, they just can't be ours
+                None => continue,
+            },
+            DeviceMatch::ByVidPid(vid, pid) => device_info.vendor_id == *vid && device_info.product_id == *pid,
+            // some devices report an empty serial rather than none, neither is a match
+            DeviceMatch::BySerial(serial) => match device_info.serial_number.as_deref() {
+                Some(number) if !number.is_empty() => number == serial,
+                _ => continue,
+            },
+        };
+
+        if is_match { matched.push(device_info); }
+    }
+
+    let filtered = matched.len();
+    matched.retain(|device_info| interface.matches(device_info));
+    if filtered > 0 && matched.is_empty() {
+        warn!("{} interface(s) match {} but none with {}, see list-devices", filtered, target, describe_interface(interface));
+    }
+
+    let mut found = by_id(matched.into_iter());
+    found.sort_by(|a, b| a.path.cmp(&b.path));
+    found
+}
+
+// A pad with several interfaces is listed once for each under the same id,
+// and only one of them sends its reports. That's the gamepad or joystick
+// one when there is one, otherwise the first listed.
+fn by_id<'a>(devices: impl Iterator<Item = &'a hidapi::HidDeviceInfo>) -> Vec<&'a hidapi::HidDeviceInfo> {
+    let mut found: Vec<&hidapi::HidDeviceInfo> = Vec::new();
+
+    for device_info in devices {
+        let pad = pad_key(device_info);
+        match found.iter().position(|other| pad_key(other) == pad) {
+            Some(i) => if is_gamepad(device_info) && !is_gamepad(found[i]) { found[i] = device_info; },
+            None => found.push(device_info),
+        }
+    }
+
+    found
+}
+
+// Generic Desktop's joystick and gamepad usages
+fn is_gamepad(device_info: &hidapi::HidDeviceInfo) -> bool {
+    device_info.usage_page == USAGE_PAGE_GENERIC_DESKTOP && [USAGE_JOYSTICK, USAGE_GAMEPAD].contains(&device_info.usage)
+}
+
+fn describe_interface(interface: &InterfaceMatch) -> String {
+    let mut parts = Vec::new();
+    if let Some(page) = interface.usage_page { parts.push(format!("usage page {:04x}", page)); }
+    if let Some(usage) = interface.usage { parts.push(format!("usage {:04x}", usage)); }
+    if let Some(number) = interface.number { parts.push(format!("interface {}", number)); }
+    parts.join(", ")
+}
+
+fn open_device(api: &hidapi::HidApi, device_info: &hidapi::HidDeviceInfo) -> Result<hidapi::HidDevice, Error> {
+    info!("Opening device...");
+
+    let device = device_info.open_device(api)?;
+    info!("Interface {}, usage page {:04x}, usage {:04x}", device_info.interface_number, device_info.usage_page, device_info.usage);
+    let manufacturer = device.get_manufacturer_string().unwrap_or_default().unwrap_or_default();
+    let product = device.get_product_string().unwrap_or_default().unwrap_or_default();
+    info!("Product: {:?}, manufacturer: {:?}", product, manufacturer);
+
+    Ok(device)
+}
+
+// Both the serial and the path, for telling matches apart in the log
+fn device_ids(device_info: &hidapi::HidDeviceInfo) -> String {
+    match device_info.serial_number.as_deref() {
+        Some(serial) if !serial.is_empty() => format!("{} ({})", serial, device_info.path.to_string_lossy()),
+        _ => device_info.path.to_string_lossy().into_owned(),
+    }
+}
+
+// What a pad's interfaces have in common: the ids and its serial, or else
+// the path up to the interface, which is per interface and comes last on
+// macOS ("...Controller@fd120000/IOUSBInterface@0/IOUSBHIDDriver") and with
+// libusb ("0001:0005:00"). A hidraw path names nothing but the node, so there
+// the interfaces of a pad without a serial each count as a pad.
+fn pad_key(device_info: &hidapi::HidDeviceInfo) -> (u16, u16, String) {
+    let pad = match device_info.serial_number.as_deref() {
+        Some(serial) if !serial.is_empty() => serial.to_string(),
+        _ => without_interface(&device_info.path.to_string_lossy()).to_string(),
+    };
+    (device_info.vendor_id, device_info.product_id, pad)
+}
+
+fn without_interface(path: &str) -> &str {
+    if let Some(at) = path.find("Interface@") {
+        return path[..at].rfind('/').map_or(path, |slash| &path[..slash]);
+    }
+
+    match path.rsplit_once(':') {
+        Some((pad, interface)) if path.matches(':').count() == 2 && interface.len() == 2 && u8::from_str_radix(interface, 16).is_ok() => pad,
+        _ => path,
+    }
+}
+
+// The id a pad keeps for the session and is found by again after a
+// reconnect. Serial numbers follow a pad to any port, but not every pad has
+// one, and then it's the path of the interface that was opened.
+fn device_id(device_info: &hidapi::HidDeviceInfo) -> String {
+    match device_info.serial_number.as_deref() {
+        Some(serial) if !serial.is_empty() => serial.to_string(),
+        _ => device_info.path.to_string_lossy().into_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CString;
+
+    use super::*;
+    use crate::sink::MemorySink;
+    use crate::sink::Sent::{KeyDown, KeyUp};
+
+    fn device(path: &str, serial: Option<&str>, usage_page: u16, usage: u16) -> hidapi::HidDeviceInfo {
+        hidapi::HidDeviceInfo {
+            path: CString::new(path).unwrap(),
+            vendor_id: 0x0f0d,
+            product_id: 0x00c1,
+            serial_number: serial.map(str::to_string),
+            release_number: 0,
+            manufacturer_string: Some("HORI CO.,LTD.".to_string()),
+            product_string: Some("HORIPAD S".to_string()),
+            usage_page,
+            usage,
+            interface_number: 0,
+        }
+    }
+
+    fn paths(found: &[&hidapi::HidDeviceInfo]) -> Vec<String> {
+        found.iter().map(|device_info| device_info.path.to_string_lossy().into_owned()).collect()
+    }
+
+    #[test]
+    fn interfaces_without_a_serial_are_one_pad() {
+        let mac = [
+            device("IOService:/XHC@14/Controller@14200000/IOUSBHostInterface@1/AppleUserUSBHostHIDDevice", None, 0x0c, 0x01),
+            device("IOService:/XHC@14/Controller@14200000/IOUSBHostInterface@0/AppleUserUSBHostHIDDevice", None, USAGE_PAGE_GENERIC_DESKTOP, USAGE_GAMEPAD),
+            device("IOService:/XHC@14/Controller@14300000/IOUSBHostInterface@0/AppleUserUSBHostHIDDevice", None, USAGE_PAGE_GENERIC_DESKTOP, USAGE_GAMEPAD),
+        ];
+        assert_eq!(paths(&by_id(mac.iter())), [
+            "IOService:/XHC@14/Controller@14200000/IOUSBHostInterface@0/AppleUserUSBHostHIDDevice",
+            "IOService:/XHC@14/Controller@14300000/IOUSBHostInterface@0/AppleUserUSBHostHIDDevice",
+        ]);
+
+        let libusb = [
+            device("0001:0005:01", None, 0x0c, 0x01),
+            device("0001:0005:00", None, USAGE_PAGE_GENERIC_DESKTOP, USAGE_JOYSTICK),
+            device("0001:0006:00", None, USAGE_PAGE_GENERIC_DESKTOP, USAGE_JOYSTICK),
+        ];
+        assert_eq!(paths(&by_id(libusb.iter())), ["0001:0005:00", "0001:0006:00"]);
+    }
+
+    #[test]
+    fn interfaces_with_a_serial_are_one_pad_on_any_path() {
+        let devices = [
+            device("/dev/hidraw1", Some("A1"), 0x0c, 0x01),
+            device("/dev/hidraw2", Some("A1"), USAGE_PAGE_GENERIC_DESKTOP, USAGE_GAMEPAD),
+            device("/dev/hidraw3", Some("B2"), USAGE_PAGE_GENERIC_DESKTOP, USAGE_GAMEPAD),
+        ];
+        assert_eq!(paths(&by_id(devices.iter())), ["/dev/hidraw2", "/dev/hidraw3"]);
+    }
+
+    #[test]
+    fn reconnect_id_is_still_per_interface() {
+        let gamepad = device("0001:0005:00", None, USAGE_PAGE_GENERIC_DESKTOP, USAGE_GAMEPAD);
+        let consumer = device("0001:0005:01", None, 0x0c, 0x01);
+        assert_eq!(pad_key(&gamepad), pad_key(&consumer));
+        assert_ne!(device_id(&gamepad), device_id(&consumer));
+    }
+
+    #[test]
+    fn dpad_positions_go_round_from_up() {
+        let expected = [Dpad::U, Dpad::UR, Dpad::R, Dpad::DR, Dpad::D, Dpad::DL, Dpad::L, Dpad::UL];
+        for (raw, &dpad) in (0..=7).zip(expected.iter()) {
+            assert_eq!(convert_dpad(raw), dpad, "raw {}", raw);
+        }
+    }
+
+    #[test]
+    fn dpad_out_of_range_is_off() {
+        for &raw in &[8, 15, 255] {
+            assert_eq!(convert_dpad(raw), Dpad::Off, "raw {}", raw);
+        }
+    }
+
+    #[test]
+    fn dpad_diagonals_are_both_directions() {
+        assert_eq!(Dpad::UR as u8, Dpad::U as u8 | Dpad::R as u8);
+        assert_eq!(Dpad::DR as u8, Dpad::D as u8 | Dpad::R as u8);
+        assert_eq!(Dpad::UL as u8, Dpad::U as u8 | Dpad::L as u8);
+        assert_eq!(Dpad::DL as u8, Dpad::D as u8 | Dpad::L as u8);
+    }
+
+    // Fills buf from each queued read in turn, leaving whatever was there
+    // past the bytes it says it read, and reads 0 bytes once they run out
+    struct MockDevice {
+        reads: std::cell::RefCell<std::collections::VecDeque<(Vec<u8>, usize)>>,
+    }
+
+    impl MockDevice {
+        fn new(reads: &[(&[u8], usize)]) -> MockDevice {
+            MockDevice { reads: std::cell::RefCell::new(reads.iter().map(|&(bytes, read)| (bytes.to_vec(), read)).collect()) }
+        }
+    }
+
+    impl ReadReport for MockDevice {
+        fn read_timeout(&self, buf: &mut [u8], _timeout: i32) -> hidapi::HidResult<usize> {
+            match self.reads.borrow_mut().pop_front() {
+                Some((bytes, read)) => {
+                    buf[..bytes.len()].copy_from_slice(&bytes);
+                    Ok(read)
+                },
+                None => Ok(0),
+            }
+        }
+    }
+
+    const A_HELD: [u8; REPORT_LEN] = [Buttons::A as u8, 0, 0x0f, 0x80, 0x80, 0x80, 0x80, 0];
+    const NEUTRAL: [u8; REPORT_LEN] = [0, 0, 0x0f, 0x80, 0x80, 0x80, 0x80, 0];
+
+    fn read(device: &MockDevice) -> Option<Input> {
+        read_input(device, Duration::ZERO, &PollOptions::default(), &mut None).unwrap()
+    }
+
+    #[test]
+    fn short_read_is_skipped_not_read_from_stale_bytes() {
+        // a full report's worth in the buffer, but only 4 bytes of it read
+        let device = MockDevice::new(&[(&A_HELD, REPORT_LEN), (&A_HELD, 4), (&NEUTRAL, REPORT_LEN)]);
+
+        assert_eq!(read(&device).map(|input| input.buttons), Some(Buttons::A as u8));
+        assert!(read(&device).is_none());
+        assert_eq!(read(&device).map(|input| input.buttons), Some(0));
+    }
+
+    #[test]
+    fn zero_byte_read_is_no_input_then_the_next_report_is_read() {
+        // what's in the buffer isn't a report when 0 bytes were read
+        let device = MockDevice::new(&[(&A_HELD, 0), (&A_HELD, REPORT_LEN)]);
+
+        assert!(read(&device).is_none());
+        assert_eq!(read(&device).map(|input| input.buttons), Some(Buttons::A as u8));
+        assert!(read(&device).is_none());
+    }
+
+    fn with_socd(socd: Socd) -> Controller {
+        Controller::new(&Config { socd, ..Config::default() }, 0)
+    }
+
+    const U: u8 = Dpad::U as u8;
+    const D: u8 = Dpad::D as u8;
+    const L: u8 = Dpad::L as u8;
+    const R: u8 = Dpad::R as u8;
+
+    #[test]
+    fn socd_neutral_lets_go_of_both() {
+        let mut controller = with_socd(Socd::Neutral);
+        assert_eq!(controller._resolve_socd(L), L);
+        assert_eq!(controller._resolve_socd(L | R), 0);
+        assert_eq!(controller._resolve_socd(R), R);
+        assert_eq!(controller._resolve_socd(U | D | R), R);
+        assert_eq!(controller._resolve_socd(0), 0);
+    }
+
+    #[test]
+    fn socd_last_input_wins() {
+        let mut controller = with_socd(Socd::LastInput);
+        assert_eq!(controller._resolve_socd(L), L);
+        assert_eq!(controller._resolve_socd(L | R), R);
+        assert_eq!(controller._resolve_socd(R), R);
+        assert_eq!(controller._resolve_socd(L | R), L);
+        // each axis goes by its own last press
+        assert_eq!(controller._resolve_socd(L | R | D), L | D);
+        assert_eq!(controller._resolve_socd(L | R | U | D), L | U);
+        assert_eq!(controller._resolve_socd(0), 0);
+    }
+
+    #[test]
+    fn socd_up_priority() {
+        let mut controller = with_socd(Socd::UpPriority);
+        assert_eq!(controller._resolve_socd(D), D);
+        assert_eq!(controller._resolve_socd(U | D), U);
+        assert_eq!(controller._resolve_socd(U), U);
+        assert_eq!(controller._resolve_socd(U | D), U);
+        // left and right still cancel out
+        assert_eq!(controller._resolve_socd(L | R), 0);
+        assert_eq!(controller._resolve_socd(L | R | U | D), U);
+    }
+
+    // Off the default center, with no deadzone to hide a stick a little off 0
+    fn inverted(swap_sticks: bool) -> Config {
+        let mut config = Config {
+            calibration: Calibration { lstick: [120, 136], rstick: [131, 124] },
+            invert: [true; 4],
+            swap_sticks,
+            debounce: None,
+            ..Config::default()
+        };
+        config.profiles[0].deadzone = 0;
+        config.profiles[0].rstick_mode = StickMode::Mouse;
+        config
+    }
+
+    #[test]
+    fn inverted_sticks_at_rest_dont_move() {
+        for &swap_sticks in &[false, true] {
+            let config = inverted(swap_sticks);
+            let mut controller = Controller::new(&config, 0);
+            let rest = Input::at_rest(&config.calibration);
+
+            assert_eq!(controller._sticks(&rest), ((0, 0), (0, 0)));
+            assert_eq!(controller._stick_velocities(0, 0), (0.0, 0.0));
+
+            let mut sink = MemorySink::default();
+            for _ in 0..3 { controller.update(Input { read_at: Instant::now(), ..rest }, &mut sink).unwrap(); }
+            assert_eq!(sink.sent, []);
+        }
+    }
+
+    #[test]
+    fn inverted_sticks_flip_around_their_center() {
+        let config = inverted(false);
+        let controller = Controller::new(&config, 0);
+        let pushed = Input { lstick1: 160, lstick2: 100, rstick1: 131, rstick2: 255, ..Input::at_rest(&config.calibration) };
+
+        assert_eq!(controller._sticks(&pushed), ((-40, 36), (0, -128)));
+    }
+
+    #[test]
+    fn nameless_devices_ahead_of_the_target_are_skipped() {
+        let nameless = hidapi::HidDeviceInfo { product_string: None, vendor_id: 0x05ac, ..device("0001:0001:00", None, 0x01, 0x06) };
+        let other = hidapi::HidDeviceInfo { product_string: Some("Magic Keyboard".to_string()), vendor_id: 0x05ac, ..device("0001:0002:00", None, 0x01, 0x06) };
+        let devices = [nameless, other, device("0001:0005:00", None, USAGE_PAGE_GENERIC_DESKTOP, USAGE_GAMEPAD)];
+
+        let found = find_targets(&devices, &DeviceMatch::ByName("HORIPAD S".to_string()), &InterfaceMatch::default());
+        assert_eq!(paths(&found), ["0001:0005:00"]);
+    }
+
+    fn axes(input: &Input, calibration: &Calibration) -> [i16; 4] {
+        [input.lx(calibration), input.ly(calibration), input.rx(calibration), input.ry(calibration)]
+    }
+
+    #[test]
+    fn sticks_at_rest_are_centered() {
+        assert_eq!(axes(&Input::default(), &Calibration::default()), [0; 4]);
+
+        for &(lstick, rstick) in &[([128, 128], [128, 128]), ([120, 136], [131, 124]), ([0, 255], [255, 0])] {
+            let calibration = Calibration { lstick, rstick };
+            assert_eq!(axes(&Input::at_rest(&calibration), &calibration), [0; 4], "{:?}", calibration);
+        }
+    }
+
+    #[test]
+    fn centered_axes_stay_within_128() {
+        let calibration = Calibration { lstick: [100, 200], rstick: [0, 255] };
+        let input = Input { lstick1: 255, lstick2: 0, rstick1: 255, rstick2: 0, ..Input::default() };
+        assert_eq!(axes(&input, &calibration), [128, -128, 128, -128]);
+    }
+
+    #[test]
+    fn wasd_sticks_at_rest_press_nothing() {
+        // far enough off 128 to press keys if they weren't centered on it
+        let calibration = Calibration { lstick: [40, 220], rstick: [220, 40] };
+        let mut config = Config { calibration, debounce: None, ..Config::default() };
+        config.profiles[0].lstick_mode = StickMode::Wasd;
+        config.profiles[0].rstick_mode = StickMode::Wasd;
+
+        let mut controller = Controller::new(&config, 0);
+        let mut sink = MemorySink::default();
+        controller.update(Input::at_rest(&calibration), &mut sink).unwrap();
+        assert_eq!(sink.sent, []);
+    }
+
+    fn bind(config: &mut Config, button: Button, keys: &[Key]) {
+        config.profiles[0].mapping[button].actions = keys.iter().map(|&key| Action::Key(key)).collect();
+    }
+
+    fn dpad(raw: u8) -> Input {
+        Input { dpad: raw, read_at: Instant::now(), ..Input::default() }
+    }
+
+    #[test]
+    fn octants_go_round_one_key_at_a_time() {
+        let mut config = Config { dpad_mode: DpadMode::Octants, debounce: None, ..Config::default() };
+        bind(&mut config, Button::UpRight, &[Key::Z]);
+        bind(&mut config, Button::DownRight, &[Key::X]);
+        bind(&mut config, Button::DownLeft, &[Key::C]);
+        bind(&mut config, Button::UpLeft, &[Key::Q]);
+
+        let mut controller = Controller::new(&config, 0);
+        let mut sink = MemorySink::default();
+        for &raw in &[0, 1, 2, 3, 4, 5, 6, 7, 0, 0x0f] {
+            controller.update(dpad(raw), &mut sink).unwrap();
+        }
+
+        assert_eq!(sink.sent, [
+            KeyDown(Key::W),
+            KeyUp(Key::W), KeyDown(Key::Z),
+            KeyUp(Key::Z), KeyDown(Key::D),
+            KeyUp(Key::D), KeyDown(Key::X),
+            KeyUp(Key::X), KeyDown(Key::S),
+            KeyUp(Key::S), KeyDown(Key::C),
+            KeyUp(Key::C), KeyDown(Key::A),
+            KeyUp(Key::A), KeyDown(Key::Q),
+            KeyUp(Key::Q), KeyDown(Key::W),
+            KeyUp(Key::W),
+        ]);
+    }
+
+    fn buttons(buttons: u8) -> Input {
+        Input { buttons, read_at: Instant::now(), ..Input::default() }
+    }
+
+    #[test]
+    fn two_buttons_on_one_key_hold_it_until_both_let_go() {
+        let mut config = Config { debounce: None, ..Config::default() };
+        bind(&mut config, Button::A, &[Key::Space]);
+        bind(&mut config, Button::B, &[Key::Space]);
+
+        let (a, b) = (Buttons::A as u8, Buttons::B as u8);
+        let mut controller = Controller::new(&config, 0);
+        let mut sink = MemorySink::default();
+        for &held in &[a, a | b, b, 0, b, a | b, a, 0] {
+            controller.update(buttons(held), &mut sink).unwrap();
+        }
+
+        assert_eq!(sink.sent, [KeyDown(Key::Space), KeyUp(Key::Space), KeyDown(Key::Space), KeyUp(Key::Space)]);
+    }
+
+    #[test]
+    fn dpad_and_wasd_stick_share_a_key() {
+        let mut config = Config { debounce: None, ..Config::default() };
+        config.profiles[0].lstick_mode = StickMode::Wasd;
+
+        let mut controller = Controller::new(&config, 0);
+        let mut sink = MemorySink::default();
+        let up = |dpad, ly| Input { dpad, lstick2: ly, read_at: Instant::now(), ..Input::default() };
+        for &input in &[up(0, 0x80), up(0, 0x00), up(0x0f, 0x00), up(0x0f, 0x80)] {
+            controller.update(input, &mut sink).unwrap();
+        }
+
+        assert_eq!(sink.sent, [KeyDown(Key::W), KeyUp(Key::W)]);
+    }
+
+    #[test]
+    fn several_matches_come_in_path_order() {
+        let devices = [
+            device("0001:0007:00", Some("C3"), USAGE_PAGE_GENERIC_DESKTOP, USAGE_GAMEPAD),
+            device("0001:0005:01", Some("A1"), 0x0c, 0x01),
+            device("0001:0006:00", Some("B2"), USAGE_PAGE_GENERIC_DESKTOP, USAGE_GAMEPAD),
+            device("0001:0005:00", Some("A1"), USAGE_PAGE_GENERIC_DESKTOP, USAGE_GAMEPAD),
+            hidapi::HidDeviceInfo { vendor_id: 0x054c, ..device("0001:0004:00", Some("D4"), USAGE_PAGE_GENERIC_DESKTOP, USAGE_GAMEPAD) },
+        ];
+        let all = InterfaceMatch::default();
+
+        let found = find_targets(&devices, &DeviceMatch::ByVidPid(0x0f0d, 0x00c1), &all);
+        assert_eq!(paths(&found), ["0001:0005:00", "0001:0006:00", "0001:0007:00"]);
+        // and in the same order however they're listed
+        let mut reversed = devices.clone();
+        reversed.reverse();
+        assert_eq!(paths(&find_targets(&reversed, &DeviceMatch::ByVidPid(0x0f0d, 0x00c1), &all)), paths(&found));
+
+        let found = find_targets(&devices, &DeviceMatch::BySerial("B2".to_string()), &all);
+        assert_eq!(paths(&found), ["0001:0006:00"]);
+
+        let consumer = InterfaceMatch { usage_page: Some(0x0c), ..InterfaceMatch::default() };
+        assert_eq!(paths(&find_targets(&devices, &DeviceMatch::ByName("HORIPAD S".to_string()), &consumer)), ["0001:0005:01"]);
+    }
+
+    #[test]
+    fn overlapping_combos_share_their_keys() {
+        let mut config = Config { debounce: None, ..Config::default() };
+        bind(&mut config, Button::A, &[Key::Control, Key::C]);
+        bind(&mut config, Button::B, &[Key::Control, Key::V]);
+        bind(&mut config, Button::X, &[Key::Shift, Key::Control, Key::C]);
+
+        let (a, b, x) = (Buttons::A as u8, Buttons::B as u8, Buttons::X as u8);
+        let mut controller = Controller::new(&config, 0);
+        let mut sink = MemorySink::default();
+        for &held in &[a, a | b, b, b | x, x, 0] {
+            controller.update(buttons(held), &mut sink).unwrap();
+        }
+
+        assert_eq!(sink.sent, [
+            // A
+            KeyDown(Key::Control), KeyDown(Key::C),
+            // B too, Control is already down
+            KeyDown(Key::V),
+            // A let go, B still holds Control
+            KeyUp(Key::C),
+            // X too, only Shift and C go down
+            KeyDown(Key::Shift), KeyDown(Key::C),
+            // B let go, X still holds Control
+            KeyUp(Key::V),
+            // X let go, in reverse
+            KeyUp(Key::C), KeyUp(Key::Control), KeyUp(Key::Shift),
+        ]);
+    }
+
+    #[test]
+    fn full_queue_keeps_connects_and_disconnects() {
+        let (tx, rx) = channel::bounded(QUEUE_SIZE, droppable);
+        tx.send((1, Report::Disconnected));
+        tx.send((1, Report::Connected("B2".to_string())));
+        for _ in 0..QUEUE_SIZE * 2 { tx.send((0, Report::Input(Input::default()))); }
+
+        let mut received = Vec::new();
+        while let Ok(item) = rx.recv_timeout(Duration::ZERO) { received.push(item); }
+        assert_eq!(received.len(), QUEUE_SIZE);
+        assert!(matches!(received[0], (1, Report::Disconnected)));
+        assert!(matches!(&received[1], (1, Report::Connected(id)) if id == "B2"));
+        assert!(received[2..].iter().all(|item| matches!(item, (0, Report::Input(_)))));
+    }
+}
@@ -0,0 +1,69 @@
+// --log-file, the log written to a file instead of stderr. Once it reaches
+// MAX_SIZE it's moved aside to PATH.1, and that one to PATH.2 and so on,
+// keeping BACKUPS of them, so a long running agent can't fill the disk.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use mac_usb_controller::error::Error;
+
+const MAX_SIZE: u64 = 10 * 1024 * 1024;
+const BACKUPS: u32 = 2;
+
+pub struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    // bytes in the current file
+    size: u64,
+}
+
+impl RotatingFile {
+    // Appends to what's already there, so restarts don't lose the last
+    // run's log
+    pub fn open(path: &Path) -> Result<RotatingFile, Error> {
+        let io_error = |error| Error::Io { context: format!("Could not open log file {:?}", path), error };
+        let file = append(path).map_err(io_error)?;
+        let size = file.metadata().map_err(io_error)?.len();
+
+        Ok(RotatingFile { path: path.to_path_buf(), file, size })
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        for i in (1..BACKUPS).rev() {
+            let from = backup(&self.path, i);
+            if from.exists() { fs::rename(&from, backup(&self.path, i + 1))?; }
+        }
+        fs::rename(&self.path, backup(&self.path, 1))?;
+
+        self.file = append(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+// Each line is written in one go, so files are only ever rotated between
+// lines
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.size > 0 && self.size + buf.len() as u64 > MAX_SIZE { self.rotate()?; }
+
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn backup(path: &Path, i: u32) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", i));
+    PathBuf::from(name)
+}
@@ -1,282 +1,225 @@
-use hidapi;
+mod args;
+mod launchd;
+mod logfile;
+
+use std::io::Write;
+use std::process;
+use std::sync::Mutex;
 use std::thread;
 use std::time::Duration;
 
-use log::{debug, error, info};
-use tfc::{Context, traits::*, Key};
-
-#[derive(Copy, Clone)]
-#[repr(u8)]
-enum Buttons {
-    Y = 0x01,
-    B = 0x02,
-    A = 0x04,
-    X = 0x08,
-    L = 0x10,
-    R = 0x20,
-    ZL = 0x40,
-    ZR = 0x80,
-}
-
-#[derive(Copy, Clone)]
-#[repr(u8)]
-enum Extra {
-    Minus = 0x01,
-    Plus = 0x02,
-    LSB = 0x04,
-    RSB = 0x08,
-    Home = 0x10,
-}
-
-#[derive(Copy, Clone)]
-#[repr(u8)]
-enum Dpad {
-    Off = 0x00,
-    U = 0x01,
-    R = 0x02,
-    D = 0x04,
-    L = 0x08,
-    UR = 0x03,
-    DR = 0x06,
-    UL = 0x09,
-    DL = 0x0C,
-}
-
-#[derive(Debug)]
-struct Input {
-    buttons: u8,
-    extra: u8,
-    dpad: u8,
-    lstick1: u8,
-    lstick2: u8,
-    rstick1: u8,
-    rstick2: u8,
-    unused: u8,
-}
-
-impl Input {
-    fn new(data: [u8; 8]) -> Input {
-        Input {
-            buttons: data[0],
-            extra: data[1],
-            dpad: data[2],
-            lstick1: data[3],
-            lstick2: data[4],
-            rstick1: data[5],
-            rstick2: data[6],
-            unused: data[7],
-        }
-    }
-
-    fn default() -> Input {
-        Input {
-            buttons: 0,
-            extra: 0,
-            dpad: 15,
-            lstick1: 128,
-            lstick2: 128,
-            rstick1: 128,
-            rstick2: 128,
-            unused: 0,
-        }
-    }
-}
-
-#[derive(Debug)]
-struct State {
-    buttons: u8,
-    extra: u8,
-    dpad: u8,
-}
+use log::{error, info, warn};
+use tfc::Context;
+
+use mac_usb_controller::apps::AppWatcher;
+use mac_usb_controller::config::{self, Config};
+use mac_usb_controller::control::Control;
+use mac_usb_controller::error::Error;
+use mac_usb_controller::overlay::Overlay;
+use mac_usb_controller::record::{Recorder, Replay};
+use mac_usb_controller::sink::{Backend, LogSink, StdoutSink, TfcSink};
+#[cfg(target_os = "linux")]
+use mac_usb_controller::uinput;
+use mac_usb_controller::watch::ConfigWatcher;
+use mac_usb_controller::{calibrate, list_devices, rebind, run_with, self_test, uptime, Source, Watchers};
+#[cfg(all(target_os = "linux", feature = "virtual-pad"))]
+use mac_usb_controller::run_pad;
+
+// tfc's X11 backend drops events sent straight after the display is opened,
+// and there's nothing to wait on instead, so this is the pause its own
+// examples use. It costs nothing noticeable on the other platforms.
+const TFC_SETTLE: Duration = Duration::from_millis(10);
+
+// Time for whatever reads input to notice a new uinput device, events sent
+// before then are lost the same way
+#[cfg(target_os = "linux")]
+const UINPUT_SETTLE: Duration = Duration::from_millis(100);
 
-impl State {
-    fn new() -> State {
-        State {
-            buttons: 0,
-            extra: 0,
-            dpad: 0,
-        }
+fn main() {
+    uptime();
+
+    // parsed before the logger exists so -v can set its level, errors are
+    // held until it does
+    let args = args::Args::parse();
+    let level = match args.as_ref().map_or(0, |args| args.verbose) {
+        0 => "info",
+        1 => "debug",
+        _ => "trace",
+    };
+    // RUST_LOG still wins when it's set
+    let mut logger = env_logger::Builder::from_env(env_logger::Env::default().filter_or(env_logger::DEFAULT_FILTER_ENV, level));
+    // a log file that can't be opened is reported on stderr instead
+    let log_file = args.as_ref().ok().and_then(|args| args.log_file.as_deref()).map(logfile::RotatingFile::open);
+    let log_file = match log_file {
+        // env_logger 0.8 sends a Target::Pipe to stderr anyway when it has
+        // colors, so each line is written to the file here and nothing is
+        // left for it to print
+        Some(Ok(file)) => {
+            let file = Mutex::new(file);
+            logger.format(move |buf, record| {
+                let mut file = file.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                let line = format!("[{} {:<5} {}] {}\n", buf.timestamp(), record.level(), record.target(), record.args());
+                file.write_all(line.as_bytes())
+            });
+            Ok(())
+        },
+        Some(Err(e)) => Err(e),
+        None => Ok(()),
+    };
+    logger.init();
+
+    if let Err(e) = log_file.and(args.map_err(Error::Usage)).and_then(try_main) {
+        error!("{}", e);
+        process::exit(e.exit_code());
     }
 }
 
-#[derive(Debug)]
-struct Controller {
-    state: State,
-}
-
-impl Controller {
-    fn new() -> Controller {
-        Controller {
-            state: State::new()
-        }
-    }
-
-    fn clear_state(&mut self) {
-        self.state.buttons = 255;
-        self.state.extra = 255;
-        self.state.dpad = 255;
-    }
-
-    fn update(&mut self, input: Input, ctx: &mut Context) -> Result<(), tfc::Error> {
-        self.state.buttons = self._handle_buttons(input.buttons, ctx)?;
-        self.state.extra = self._handle_extra(input.extra, ctx)?;
-        self.state.dpad = self._handle_dpad(input.dpad, ctx)?;
-
-        Ok(())
-    }
-
-    fn _handle_buttons(&self, buttons: u8, ctx: &mut Context) -> Result<u8, tfc::Error> {
-        let mut s: u8 = 0;
-        let diff = buttons ^ self.state.buttons;
-
-        s |= self._check_key(buttons, diff, Buttons::Y as u8, Key::P, ctx)?;
-        s |= self._check_key(buttons, diff, Buttons::B as u8, Key::O, ctx)?;
-        s |= self._check_key(buttons, diff, Buttons::A as u8, Key::I, ctx)?;
-        s |= self._check_key(buttons, diff, Buttons::X as u8, Key::U, ctx)?;
-        s |= self._check_key(buttons, diff, Buttons::L as u8, Key::Y, ctx)?;
-        s |= self._check_key(buttons, diff, Buttons::R as u8, Key::T, ctx)?;
-        s |= self._check_key(buttons, diff, Buttons::ZL as u8, Key::R, ctx)?;
-        s |= self._check_key(buttons, diff, Buttons::ZR as u8, Key::E, ctx)?;
-
-        Ok(s)
+fn try_main(args: args::Args) -> Result<(), Error> {
+    if let Err(e) = ctrlc::set_handler(mac_usb_controller::stop) {
+        error!("Could not install signal handler: {:?}", e);
     }
 
-    fn _handle_extra(&self, extra: u8, ctx: &mut Context) -> Result<u8, tfc::Error> {
-        let mut s: u8 = 0;
-        let diff = extra ^ self.state.extra;
-
-        s |= self._check_key(extra, diff, Extra::Minus as u8, Key::L, ctx)?;
-        s |= self._check_key(extra, diff, Extra::Plus as u8, Key::K, ctx)?;
-        s |= self._check_key(extra, diff, Extra::LSB as u8, Key::J, ctx)?;
-        s |= self._check_key(extra, diff, Extra::RSB as u8, Key::H, ctx)?;
-        s |= self._check_key(extra, diff, Extra::Home as u8, Key::G, ctx)?;
-
-        Ok(s)
+    // problems go through the usual error path, so a bad config exits 3
+    if let Some(path) = &args.check_config {
+        let config = config::load(path)?;
+        for warning in config.warnings() { println!("warning: {}", warning); }
+        println!("OK");
+        return Ok(());
     }
 
-    fn _handle_dpad(&self, dpad: u8, ctx: &mut Context) -> Result<u8, tfc::Error> {
-        let mut s: u8 = 0;
-        let cleaned = self._convert_dpad(dpad) as u8;
-        let diff = cleaned ^ self.state.dpad;
+    if let Some(flags) = &args.install { return launchd::install(flags); }
+    if args.uninstall { return launchd::uninstall(); }
 
-        s |= self._check_key(cleaned, diff, Dpad::U as u8, Key::W, ctx)?;
-        s |= self._check_key(cleaned, diff, Dpad::D as u8, Key::S, ctx)?;
-        s |= self._check_key(cleaned, diff, Dpad::L as u8, Key::A, ctx)?;
-        s |= self._check_key(cleaned, diff, Dpad::R as u8, Key::D, ctx)?;
-
-        Ok(s)
+    if args.list_devices {
+        list_devices(&hidapi::HidApi::new()?);
+        return Ok(());
     }
 
-    fn _check_key(&self, input: u8, diff: u8, button: u8, key: Key, ctx: &mut Context) -> Result<u8, tfc::Error> {
-        if diff & button != 0 {
-            let d = input & button;
-
-            if d != 0 { ctx.key_down(key)?; }
-            else { ctx.key_up(key)?; }
-
-            return Ok(d);
-        }
-
-        Ok(0)
-    }
-
-    // The actual dpad input values just increase sequentially
-    // Here we convert them to a nicer bitwise format
-    fn _convert_dpad(&self, dpad: u8) -> Dpad {
-        match dpad {
-            0 => Dpad::U,
-            1 => Dpad::UR,
-            2 => Dpad::R,
-            3 => Dpad::DR,
-            4 => Dpad::D,
-            5 => Dpad::DL,
-            6 => Dpad::L,
-            7 => Dpad::UL,
-            _ => Dpad::Off,
-        }
-    }
-}
-
-fn main() {
-    env_logger::init_from_env(env_logger::Env::default().filter_or(env_logger::DEFAULT_FILTER_ENV, "info"));
+    if args.test { return self_test(&args.target, &args.poll); }
 
-    let target = "HORIPAD S";
-    let mut ctx = Context::new().unwrap();
-    thread::sleep(Duration::from_millis(10));
+    if args.calibrate {
+        let calibration = calibrate(&args.target, &args.poll)?;
 
-    match hidapi::HidApi::new() {
-        Ok(api) => {
-            match open_target(&api, target) {
-                Some(device) => { poll(&device, &mut ctx) },
-                None => {}
-            };
+        match &args.config {
+            Some(path) => {
+                config::save_calibration(path, &calibration).map_err(|error| Error::Io {
+                    context: format!("Could not save calibration to {:?}", path),
+                    error,
+                })?;
+                info!("Saved calibration to {:?}", path);
+            },
+            None => print!("{}", calibration),
         }
-        Err(e) => error!("Error connecting device {:?}", e)
+        return Ok(());
     }
 
-    info!("Shutting down...")
-}
-
-fn poll(device: &hidapi::HidDevice, ctx: &mut Context) {
-    info!("Polling Device...");
-    let mut controller = Controller::new();
-    let mut i: u8 = 0;
-
-    loop {
-        // need to clear every so often to handle dropped inputs
-        if i % 7 == 0 { controller.clear_state() }
-        i = i.wrapping_add(1);
-
-        match read_input(device) {
-            Ok(input) => { 
-                match controller.update(input, ctx) {
-                    Ok(_) => continue,
-                    Err(_) => controller.clear_state()
-                }
+    if args.rebind {
+        let buttons = rebind(&args.target, &args.poll)?;
+        if buttons.bindings.is_empty() { return Ok(()); }
+
+        match &args.config {
+            Some(path) => {
+                config::save_buttons(path, &buttons).map_err(|error| Error::Io {
+                    context: format!("Could not save buttons to {:?}", path),
+                    error,
+                })?;
+                info!("Saved buttons to {:?}", path);
             },
-            Err(_) => { 
-                // assume no input
-                match controller.update(Input::default(), ctx) {
-                    Ok(_) => continue,
-                    Err(_) => controller.clear_state()
-                }
-            }
+            None => print!("{}", buttons),
         }
+        return Ok(());
     }
-}
-
-fn read_input(device: &hidapi::HidDevice) -> Result<Input, hidapi::HidError> {
-    // Read data from device
-    let mut buf = [0u8; 8];
 
-    device.read(&mut buf[..])?;
-    let input = Input::new(buf);
-    debug!("Read: {:?}", &input);
-
-    thread::sleep(Duration::from_millis(1));
-    Ok(input)
-}
-
-fn open_target(api: &hidapi::HidApi, target: &str) -> Option<hidapi::HidDevice> {
-    for device_info in api.devices() {
-        if device_info.product_string.as_ref()? == target {
-            info!("Opening device...");
-
-            return match device_info.open_device(api) {
-                Ok(device) => {
-                    let manufacturer = device.get_manufacturer_string().unwrap_or_default().unwrap_or_default();
-                    let product = device.get_product_string().unwrap_or_default().unwrap_or_default();
-                    info!("Product: {:?}, manufacturer: {:?}", product, manufacturer);
-                    Some(device)
-                },
-                Err(e) => {
-                    error!("Could not open device: {:?}", e);
-                    None
-                }
-            };
+    let config = match &args.config {
+        Some(path) => config::load(path)?,
+        None => Config::default(),
+    };
+
+    let recorder = match &args.record {
+        Some(path) => Some(Recorder::create(path).map_err(|error| Error::Io {
+            context: format!("Could not create recording {:?}", path),
+            error,
+        })?),
+        None => None,
+    };
+
+    let mut overlay = match &args.overlay {
+        Some(addr) => Some(Overlay::start(addr).map_err(|error| Error::Io {
+            context: format!("Could not start overlay on {}", addr),
+            error,
+        })?),
+        None => None,
+    };
+
+    // only worth watching if some profile or focus_apps asks for it
+    let apps = if config.watches_focus() {
+        match AppWatcher::start() {
+            Ok(watcher) => Some(watcher),
+            Err(e) => {
+                warn!("Profiles and focus_apps won't follow the focused app: {}", e);
+                None
+            },
         }
-    }
-
-    error!("Unable to find provided target {:?}", target);
-    None
+    } else {
+        None
+    };
+
+    let watcher = match &args.config {
+        Some(path) if args.watch => Some(ConfigWatcher::start(path).map_err(|error| Error::Io {
+            context: format!("Could not watch config {:?}", path),
+            error,
+        })?),
+        _ => None,
+    };
+
+    let control = match &args.control {
+        Some(path) => Some(Control::start(path, args.config.clone()).map_err(|error| Error::Io {
+            context: format!("Could not start the control socket {:?}", path),
+            error,
+        })?),
+        None => None,
+    };
+    let watchers = Watchers { apps, config: watcher, control };
+
+    let source = match &args.replay {
+        Some(path) => Source::Replay(Replay::open(path).map_err(|error| Error::Io {
+            context: format!("Could not open recording {:?}", path),
+            error,
+        })?),
+        None => Source::Device { target: args.target.clone(), recorder },
+    };
+
+    let result = match args.backend {
+        _ if args.dry_run => {
+            info!("Dry run, key events will only be logged");
+            run_with(source, config, &args.poll, &mut overlay, &watchers, &mut LogSink)
+        },
+        Backend::Tfc => {
+            let mut sink = TfcSink::new(Context::new().map_err(Error::Backend)?);
+            thread::sleep(TFC_SETTLE);
+            run_with(source, config, &args.poll, &mut overlay, &watchers, &mut sink)
+        },
+        Backend::Stdout => run_with(source, config, &args.poll, &mut overlay, &watchers, &mut StdoutSink::default()),
+        #[cfg(target_os = "linux")]
+        Backend::Uinput => {
+            let mut sink = uinput::UinputSink::new().map_err(|error| Error::Io {
+                context: "Could not create a uinput device".to_string(),
+                error,
+            })?;
+            thread::sleep(UINPUT_SETTLE);
+            run_with(source, config, &args.poll, &mut overlay, &watchers, &mut sink)
+        },
+        #[cfg(all(target_os = "linux", feature = "virtual-pad"))]
+        Backend::Pad => {
+            let mut pad = uinput::UinputPad::new().map_err(|error| Error::Io {
+                context: "Could not create a uinput gamepad".to_string(),
+                error,
+            })?;
+            thread::sleep(UINPUT_SETTLE);
+            run_pad(source, &config, &args.poll, &mut pad)
+        },
+    };
+
+    info!("Shutting down...");
+    result
 }
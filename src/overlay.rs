@@ -0,0 +1,128 @@
+use serde::{Deserialize, Serialize};
+
+// What the overlay is sent, as JSON, whenever it changes. input-display
+// reads it back.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub profile: String,
+    // held buttons, named as in the config
+    pub buttons: Vec<String>,
+    // raw 0-255 axes, 128 is centered
+    pub lstick: [u8; 2],
+    pub rstick: [u8; 2],
+    // ZL and ZR pressure, only in analog mode
+    pub triggers: Option<[u8; 2]>,
+}
+
+#[cfg(feature = "overlay")]
+pub use server::Overlay;
+
+// Without the feature an overlay can never be started, so there's nothing to publish to
+#[cfg(not(feature = "overlay"))]
+pub enum Overlay {}
+
+#[cfg(not(feature = "overlay"))]
+impl Overlay {
+    pub fn start(_addr: &str) -> std::io::Result<Overlay> {
+        Err(std::io::Error::other("built without the overlay feature"))
+    }
+
+    pub fn publish(&mut self, _snapshot: Snapshot) {
+        match *self {}
+    }
+}
+
+#[cfg(feature = "overlay")]
+mod server {
+    use std::io;
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    use log::{debug, info, warn};
+    use tungstenite::Message;
+
+    use super::Snapshot;
+
+    // How often each client checks for a new snapshot
+    const CLIENT_INTERVAL: Duration = Duration::from_millis(5);
+
+    // The newest snapshot as JSON, with a count so clients can tell it changed
+    type Latest = Arc<Mutex<(u64, String)>>;
+
+    // Publishing only swaps the shared JSON, the sockets are all written on
+    // their own threads so a slow client never holds up dispatch
+    pub struct Overlay {
+        last: Option<Snapshot>,
+        latest: Latest,
+    }
+
+    impl Overlay {
+        pub fn start(addr: &str) -> io::Result<Overlay> {
+            let listener = TcpListener::bind(addr)?;
+            let latest: Latest = Arc::new(Mutex::new((0, String::new())));
+            info!("Overlay listening on ws://{}", addr);
+
+            let shared = latest.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    match stream {
+                        Ok(stream) => {
+                            let latest = shared.clone();
+                            thread::spawn(move || serve(stream, latest));
+                        },
+                        Err(e) => warn!("Overlay connection failed: {}", e),
+                    }
+                }
+            });
+
+            Ok(Overlay { last: None, latest })
+        }
+
+        pub fn publish(&mut self, snapshot: Snapshot) {
+            if self.last.as_ref() == Some(&snapshot) { return; }
+
+            match serde_json::to_string(&snapshot) {
+                Ok(json) => {
+                    let mut latest = self.latest.lock().unwrap();
+                    *latest = (latest.0 + 1, json);
+                },
+                Err(e) => warn!("Could not encode overlay snapshot: {}", e),
+            }
+
+            self.last = Some(snapshot);
+        }
+    }
+
+    // Sends the current snapshot straight away, then every new one until the
+    // client goes away
+    fn serve(stream: TcpStream, latest: Latest) {
+        let mut socket = match tungstenite::accept(stream) {
+            Ok(socket) => socket,
+            Err(e) => {
+                warn!("Overlay handshake failed: {}", e);
+                return;
+            }
+        };
+        debug!("Overlay client connected");
+
+        let mut seen = 0;
+        loop {
+            let update = {
+                let latest = latest.lock().unwrap();
+                if latest.0 != seen { Some(latest.clone()) } else { None }
+            };
+
+            if let Some((version, json)) = update {
+                if let Err(e) = socket.write_message(Message::Text(json)) {
+                    debug!("Overlay client gone: {}", e);
+                    return;
+                }
+                seen = version;
+            }
+
+            thread::sleep(CLIENT_INTERVAL);
+        }
+    }
+}
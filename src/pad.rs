@@ -0,0 +1,57 @@
+// The pad passed through as a gamepad rather than mapped to keys, for apps
+// that don't know the HORIPAD but would take a gamepad they do know. run_pad
+// feeds a PadSink each report's PadState, and the sink turns that into
+// whatever virtual device it makes.
+
+use crate::config::{Button, Calibration};
+use crate::Input;
+
+// One report as a gamepad sees it
+#[derive(Debug, Clone, PartialEq)]
+pub struct PadState {
+    // after pad_buttons, the dpad left out as it's the hat
+    pub buttons: Vec<Button>,
+    // -1, 0 or 1 on each axis, positive right and down
+    pub hat: (i32, i32),
+    // lx ly rx ry, -128 to 128 from the calibrated center
+    pub sticks: [i32; 4],
+    // 0-255, full or nothing when the pad isn't in analog mode
+    pub triggers: (u8, u8),
+}
+
+impl PadState {
+    // `remap` is pad_buttons, each button listed there showing up as the
+    // other and the rest as themselves
+    pub fn new(input: &Input, calibration: &Calibration, remap: &[(Button, Button)]) -> PadState {
+        let pressed = input.pressed();
+        let held = |button: Button| pressed.contains(&button);
+
+        let mut buttons: Vec<Button> = pressed.iter()
+            .filter(|&&button| !matches!(button, Button::Up | Button::Down | Button::Left | Button::Right))
+            .map(|&button| remap.iter().find(|&&(from, _)| from == button).map_or(button, |&(_, to)| to))
+            .collect();
+        buttons.sort_by_key(|&button| button as usize);
+        buttons.dedup();
+
+        let axis = |minus: Button, plus: Button| held(plus) as i32 - held(minus) as i32;
+        let full = |button: Button| if held(button) { u8::MAX } else { 0 };
+
+        PadState {
+            buttons,
+            hat: (axis(Button::Left, Button::Right), axis(Button::Up, Button::Down)),
+            sticks: [
+                input.lx(calibration) as i32,
+                input.ly(calibration) as i32,
+                input.rx(calibration) as i32,
+                input.ry(calibration) as i32,
+            ],
+            triggers: input.triggers.unwrap_or((full(Button::ZL), full(Button::ZR))),
+        }
+    }
+}
+
+pub trait PadSink {
+    // Called with every report and again with the pad at rest when it's
+    // lost or we're shutting down, states that haven't changed included
+    fn send(&mut self, state: &PadState) -> Result<(), tfc::Error>;
+}
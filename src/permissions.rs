@@ -0,0 +1,16 @@
+// macOS silently drops or refuses synthesized events unless the binary (or
+// the terminal running it) has been granted Accessibility access
+#[cfg(target_os = "macos")]
+pub fn accessibility_granted() -> bool {
+    #[link(name = "ApplicationServices", kind = "framework")]
+    extern "C" {
+        fn AXIsProcessTrusted() -> u8;
+    }
+
+    unsafe { AXIsProcessTrusted() != 0 }
+}
+
+#[cfg(not(target_os = "macos"))]
+pub fn accessibility_granted() -> bool {
+    true
+}
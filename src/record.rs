@@ -0,0 +1,93 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Lines, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+// Recordings are plain text, one report per line: microseconds since the
+// recording started, then the raw report bytes in hex, e.g.
+// "1520 00 00 0f 80 80 80 80 00"
+pub struct Recorder {
+    out: BufWriter<File>,
+    start: Instant,
+}
+
+impl Recorder {
+    pub fn create(path: &Path) -> io::Result<Recorder> {
+        Ok(Recorder {
+            out: BufWriter::new(File::create(path)?),
+            start: Instant::now(),
+        })
+    }
+
+    pub fn record(&mut self, data: &[u8]) -> io::Result<()> {
+        write!(self.out, "{}", self.start.elapsed().as_micros())?;
+        for byte in data { write!(self.out, " {:02x}", byte)?; }
+        writeln!(self.out)
+    }
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(io::Error),
+    Corrupt { line: usize, reason: String },
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::Io(e) => write!(f, "Could not read recording: {}", e),
+            ReplayError::Corrupt { line, reason } => write!(f, "Corrupt recording on line {}: {}", line, reason),
+        }
+    }
+}
+
+// Reads a recording back as (time since start, raw report) pairs
+pub struct Replay {
+    lines: Lines<BufReader<File>>,
+    line: usize,
+}
+
+impl Replay {
+    pub fn open(path: &Path) -> io::Result<Replay> {
+        Ok(Replay {
+            lines: BufReader::new(File::open(path)?).lines(),
+            line: 0,
+        })
+    }
+
+    fn parse(&self, text: &str) -> Result<(Duration, Vec<u8>), ReplayError> {
+        let corrupt = |reason: String| ReplayError::Corrupt { line: self.line, reason };
+        let mut fields = text.split_whitespace();
+
+        let time = fields.next().ok_or_else(|| corrupt("missing timestamp".to_string()))?;
+        let micros = time.parse().map_err(|_| corrupt(format!("bad timestamp {:?}", time)))?;
+
+        let data = fields
+            .map(|byte| match byte.len() {
+                2 => u8::from_str_radix(byte, 16).map_err(|_| corrupt(format!("bad byte {:?}", byte))),
+                _ => Err(corrupt(format!("bad byte {:?}", byte))),
+            })
+            .collect::<Result<Vec<u8>, ReplayError>>()?;
+
+        if data.is_empty() { return Err(corrupt("no report bytes".to_string())); }
+
+        Ok((Duration::from_micros(micros), data))
+    }
+}
+
+impl Iterator for Replay {
+    type Item = Result<(Duration, Vec<u8>), ReplayError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let text = match self.lines.next()? {
+                Ok(text) => text,
+                Err(e) => return Some(Err(ReplayError::Io(e))),
+            };
+            self.line += 1;
+
+            if !text.trim().is_empty() { return Some(self.parse(&text)); }
+        }
+    }
+}
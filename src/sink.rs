@@ -0,0 +1,332 @@
+use std::fmt;
+use std::io::{self, Write};
+use std::time::Instant;
+
+use log::{debug, info};
+use serde::Serialize;
+use tfc::{Context, traits::*, Enum, Key, MouseButton};
+
+// Where the controller's key and mouse events end up. Controller only
+// talks to this, so it can be driven without a real keyboard backend.
+pub trait OutputSink {
+    fn key_down(&mut self, key: Key) -> Result<(), tfc::Error>;
+    fn key_up(&mut self, key: Key) -> Result<(), tfc::Error>;
+    fn mouse_down(&mut self, button: MouseButton) -> Result<(), tfc::Error>;
+    fn mouse_up(&mut self, button: MouseButton) -> Result<(), tfc::Error>;
+    fn mouse_move_rel(&mut self, dx: i32, dy: i32) -> Result<(), tfc::Error>;
+    fn mouse_scroll(&mut self, dx: i32, dy: i32) -> Result<(), tfc::Error>;
+    fn type_text(&mut self, text: &str) -> Result<(), tfc::Error>;
+
+    // Sinks that can send several events at once hold them until this, at
+    // the end of each Controller::update. The rest send them straight away.
+    fn flush(&mut self) -> Result<(), tfc::Error> {
+        Ok(())
+    }
+}
+
+// Sends events to the OS through tfc
+pub struct TfcSink {
+    ctx: Context,
+}
+
+impl TfcSink {
+    pub fn new(ctx: Context) -> TfcSink {
+        TfcSink { ctx }
+    }
+}
+
+impl OutputSink for TfcSink {
+    fn key_down(&mut self, key: Key) -> Result<(), tfc::Error> {
+        self.ctx.key_down(key)
+    }
+
+    fn key_up(&mut self, key: Key) -> Result<(), tfc::Error> {
+        self.ctx.key_up(key)
+    }
+
+    fn mouse_down(&mut self, button: MouseButton) -> Result<(), tfc::Error> {
+        self.ctx.mouse_down(button)
+    }
+
+    fn mouse_up(&mut self, button: MouseButton) -> Result<(), tfc::Error> {
+        self.ctx.mouse_up(button)
+    }
+
+    fn mouse_move_rel(&mut self, dx: i32, dy: i32) -> Result<(), tfc::Error> {
+        self.ctx.mouse_move_rel(dx, dy)
+    }
+
+    fn mouse_scroll(&mut self, dx: i32, dy: i32) -> Result<(), tfc::Error> {
+        self.ctx.mouse_scroll(dx, dy)
+    }
+
+    fn type_text(&mut self, text: &str) -> Result<(), tfc::Error> {
+        self.ctx.unicode_string(text)
+    }
+}
+
+// Which sink --backend picked
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Backend {
+    Tfc,
+    Stdout,
+    #[cfg(target_os = "linux")]
+    Uinput,
+    // a virtual gamepad instead of keys and mouse, see run_pad
+    #[cfg(all(target_os = "linux", feature = "virtual-pad"))]
+    Pad,
+}
+
+// Writes each event to stdout as a line of JSON instead of sending it,
+// e.g. {"event":"key_down","key":"Space"}, for piping into other tools.
+// The log goes to stderr so it stays out of the way. Each update's lines
+// are written together.
+#[derive(Default)]
+pub struct StdoutSink {
+    pending: String,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Event<'a> {
+    KeyDown { key: &'static str },
+    KeyUp { key: &'static str },
+    MouseDown { button: &'static str },
+    MouseUp { button: &'static str },
+    MouseMove { dx: i32, dy: i32 },
+    MouseScroll { dx: i32, dy: i32 },
+    TypeText { text: &'a str },
+}
+
+impl StdoutSink {
+    fn write(&mut self, event: Event) -> Result<(), tfc::Error> {
+        match serde_json::to_string(&event) {
+            Ok(line) => {
+                self.pending.push_str(&line);
+                self.pending.push('\n');
+                Ok(())
+            },
+            Err(e) => {
+                debug!("Could not write event to stdout: {}", e);
+                Err(tfc::Error::Unknown)
+            },
+        }
+    }
+}
+
+impl OutputSink for StdoutSink {
+    fn key_down(&mut self, key: Key) -> Result<(), tfc::Error> {
+        self.write(Event::KeyDown { key: key.identifier_name() })
+    }
+
+    fn key_up(&mut self, key: Key) -> Result<(), tfc::Error> {
+        self.write(Event::KeyUp { key: key.identifier_name() })
+    }
+
+    fn mouse_down(&mut self, button: MouseButton) -> Result<(), tfc::Error> {
+        self.write(Event::MouseDown { button: button.identifier_name() })
+    }
+
+    fn mouse_up(&mut self, button: MouseButton) -> Result<(), tfc::Error> {
+        self.write(Event::MouseUp { button: button.identifier_name() })
+    }
+
+    fn mouse_move_rel(&mut self, dx: i32, dy: i32) -> Result<(), tfc::Error> {
+        self.write(Event::MouseMove { dx, dy })
+    }
+
+    fn mouse_scroll(&mut self, dx: i32, dy: i32) -> Result<(), tfc::Error> {
+        self.write(Event::MouseScroll { dx, dy })
+    }
+
+    fn type_text(&mut self, text: &str) -> Result<(), tfc::Error> {
+        self.write(Event::TypeText { text })
+    }
+
+    fn flush(&mut self) -> Result<(), tfc::Error> {
+        if self.pending.is_empty() { return Ok(()); }
+
+        let mut stdout = io::stdout().lock();
+        let written = stdout.write_all(self.pending.as_bytes()).and_then(|_| stdout.flush());
+        self.pending.clear();
+
+        // tfc has nowhere to put an io error, so it's only logged here
+        written.map_err(|e| {
+            debug!("Could not write events to stdout: {}", e);
+            tfc::Error::Unknown
+        })
+    }
+}
+
+// Only logs what would have been sent, for trying out a mapping with --dry-run
+pub struct LogSink;
+
+impl OutputSink for LogSink {
+    fn key_down(&mut self, key: Key) -> Result<(), tfc::Error> {
+        info!("key_down {}", key.identifier_name());
+        Ok(())
+    }
+
+    fn key_up(&mut self, key: Key) -> Result<(), tfc::Error> {
+        info!("key_up {}", key.identifier_name());
+        Ok(())
+    }
+
+    fn mouse_down(&mut self, button: MouseButton) -> Result<(), tfc::Error> {
+        info!("mouse_down {}", button.identifier_name());
+        Ok(())
+    }
+
+    fn mouse_up(&mut self, button: MouseButton) -> Result<(), tfc::Error> {
+        info!("mouse_up {}", button.identifier_name());
+        Ok(())
+    }
+
+    // debug rather than info, the sticks send these every poll
+    fn mouse_move_rel(&mut self, dx: i32, dy: i32) -> Result<(), tfc::Error> {
+        debug!("mouse_move_rel {} {}", dx, dy);
+        Ok(())
+    }
+
+    fn mouse_scroll(&mut self, dx: i32, dy: i32) -> Result<(), tfc::Error> {
+        debug!("mouse_scroll {} {}", dx, dy);
+        Ok(())
+    }
+
+    fn type_text(&mut self, text: &str) -> Result<(), tfc::Error> {
+        info!("type_text {:?}", text);
+        Ok(())
+    }
+}
+
+// One event a MemorySink was sent, written the way LogSink logs it
+#[derive(Debug, Clone, PartialEq)]
+pub enum Sent {
+    KeyDown(Key),
+    KeyUp(Key),
+    MouseDown(MouseButton),
+    MouseUp(MouseButton),
+    MouseMove(i32, i32),
+    MouseScroll(i32, i32),
+    TypeText(String),
+}
+
+impl fmt::Display for Sent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Sent::KeyDown(key) => write!(f, "key_down {}", key.identifier_name()),
+            Sent::KeyUp(key) => write!(f, "key_up {}", key.identifier_name()),
+            Sent::MouseDown(button) => write!(f, "mouse_down {}", button.identifier_name()),
+            Sent::MouseUp(button) => write!(f, "mouse_up {}", button.identifier_name()),
+            Sent::MouseMove(dx, dy) => write!(f, "mouse_move_rel {} {}", dx, dy),
+            Sent::MouseScroll(dx, dy) => write!(f, "mouse_scroll {} {}", dx, dy),
+            Sent::TypeText(text) => write!(f, "type_text {:?}", text),
+        }
+    }
+}
+
+// Keeps everything it's sent in order, for driving a Controller from tests
+// and anything else that wants to look at the events rather than send them
+#[derive(Debug, Default)]
+pub struct MemorySink {
+    pub sent: Vec<Sent>,
+}
+
+impl OutputSink for MemorySink {
+    fn key_down(&mut self, key: Key) -> Result<(), tfc::Error> {
+        self.sent.push(Sent::KeyDown(key));
+        Ok(())
+    }
+
+    fn key_up(&mut self, key: Key) -> Result<(), tfc::Error> {
+        self.sent.push(Sent::KeyUp(key));
+        Ok(())
+    }
+
+    fn mouse_down(&mut self, button: MouseButton) -> Result<(), tfc::Error> {
+        self.sent.push(Sent::MouseDown(button));
+        Ok(())
+    }
+
+    fn mouse_up(&mut self, button: MouseButton) -> Result<(), tfc::Error> {
+        self.sent.push(Sent::MouseUp(button));
+        Ok(())
+    }
+
+    fn mouse_move_rel(&mut self, dx: i32, dy: i32) -> Result<(), tfc::Error> {
+        self.sent.push(Sent::MouseMove(dx, dy));
+        Ok(())
+    }
+
+    fn mouse_scroll(&mut self, dx: i32, dy: i32) -> Result<(), tfc::Error> {
+        self.sent.push(Sent::MouseScroll(dx, dy));
+        Ok(())
+    }
+
+    fn type_text(&mut self, text: &str) -> Result<(), tfc::Error> {
+        self.sent.push(Sent::TypeText(text.to_string()));
+        Ok(())
+    }
+}
+
+// Passes everything on to another sink, noting when the last event went
+// out, for measuring latency
+pub struct TimedSink<'a, S> {
+    inner: &'a mut S,
+    pub last_event: Option<Instant>,
+}
+
+impl<'a, S: OutputSink> TimedSink<'a, S> {
+    pub fn new(inner: &'a mut S) -> TimedSink<'a, S> {
+        TimedSink { inner, last_event: None }
+    }
+
+    fn sent(&mut self, result: Result<(), tfc::Error>) -> Result<(), tfc::Error> {
+        if result.is_ok() { self.last_event = Some(Instant::now()); }
+        result
+    }
+}
+
+impl<'a, S: OutputSink> OutputSink for TimedSink<'a, S> {
+    fn key_down(&mut self, key: Key) -> Result<(), tfc::Error> {
+        let result = self.inner.key_down(key);
+        self.sent(result)
+    }
+
+    fn key_up(&mut self, key: Key) -> Result<(), tfc::Error> {
+        let result = self.inner.key_up(key);
+        self.sent(result)
+    }
+
+    fn mouse_down(&mut self, button: MouseButton) -> Result<(), tfc::Error> {
+        let result = self.inner.mouse_down(button);
+        self.sent(result)
+    }
+
+    fn mouse_up(&mut self, button: MouseButton) -> Result<(), tfc::Error> {
+        let result = self.inner.mouse_up(button);
+        self.sent(result)
+    }
+
+    fn mouse_move_rel(&mut self, dx: i32, dy: i32) -> Result<(), tfc::Error> {
+        let result = self.inner.mouse_move_rel(dx, dy);
+        self.sent(result)
+    }
+
+    fn mouse_scroll(&mut self, dx: i32, dy: i32) -> Result<(), tfc::Error> {
+        let result = self.inner.mouse_scroll(dx, dy);
+        self.sent(result)
+    }
+
+    fn type_text(&mut self, text: &str) -> Result<(), tfc::Error> {
+        let result = self.inner.type_text(text);
+        self.sent(result)
+    }
+
+    // a batching sink only really sends anything here
+    fn flush(&mut self) -> Result<(), tfc::Error> {
+        let sent = self.last_event.is_some();
+        let result = self.inner.flush();
+        if sent { self.sent(result) } else { result }
+    }
+}
@@ -0,0 +1,462 @@
+// A virtual keyboard and mouse made through /dev/uinput, for --backend
+// uinput on Linux, and with the virtual-pad feature a virtual gamepad for
+// --backend pad. The user running it needs write access to /dev/uinput,
+// usually through the input group or a udev rule.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::mem;
+use std::os::raw::{c_int, c_ulong};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+
+use log::debug;
+use tfc::{Enum, Key, MouseButton};
+
+#[cfg(feature = "virtual-pad")]
+use crate::config::Button;
+#[cfg(feature = "virtual-pad")]
+use crate::pad::{PadSink, PadState};
+use crate::sink::OutputSink;
+
+const O_NONBLOCK: c_int = 0o4000;
+
+// from linux/uinput.h, 'U' is the ioctl type
+const UI_DEV_CREATE: c_ulong = 0x5501;
+const UI_DEV_DESTROY: c_ulong = 0x5502;
+const UI_DEV_SETUP: c_ulong = 0x405c_5503;
+const UI_SET_EVBIT: c_ulong = 0x4004_5564;
+const UI_SET_KEYBIT: c_ulong = 0x4004_5565;
+const UI_SET_RELBIT: c_ulong = 0x4004_5566;
+#[cfg(feature = "virtual-pad")]
+const UI_SET_ABSBIT: c_ulong = 0x4004_5567;
+#[cfg(feature = "virtual-pad")]
+const UI_ABS_SETUP: c_ulong = 0x401c_5504;
+
+// from linux/input-event-codes.h
+const EV_SYN: u16 = 0x00;
+const EV_KEY: u16 = 0x01;
+const EV_REL: u16 = 0x02;
+const SYN_REPORT: u16 = 0;
+const REL_X: u16 = 0x00;
+const REL_Y: u16 = 0x01;
+const REL_HWHEEL: u16 = 0x06;
+const REL_WHEEL: u16 = 0x08;
+const BTN_LEFT: u16 = 0x110;
+const BTN_RIGHT: u16 = 0x111;
+const BTN_MIDDLE: u16 = 0x112;
+const BUS_USB: u16 = 0x03;
+#[cfg(feature = "virtual-pad")]
+const EV_ABS: u16 = 0x03;
+
+// A gamepad's axes, sticks then triggers then the hat, with their range
+#[cfg(feature = "virtual-pad")]
+const PAD_AXES: [(u16, i32, i32); 8] = [
+    (0x00, -128, 128), // ABS_X
+    (0x01, -128, 128), // ABS_Y
+    (0x03, -128, 128), // ABS_RX
+    (0x04, -128, 128), // ABS_RY
+    (0x02, 0, 255),    // ABS_Z
+    (0x05, 0, 255),    // ABS_RZ
+    (0x10, -1, 1),     // ABS_HAT0X
+    (0x11, -1, 1),     // ABS_HAT0Y
+];
+
+// Scrolls come in tfc's units, 120 to a wheel notch, the way tfc's own
+// Linux backend counts them
+const SCROLL_PER_NOTCH: i32 = 120;
+
+#[repr(C)]
+struct InputId {
+    bustype: u16,
+    vendor: u16,
+    product: u16,
+    version: u16,
+}
+
+#[repr(C)]
+struct UinputSetup {
+    id: InputId,
+    name: [u8; 80],
+    ff_effects_max: u32,
+}
+
+#[cfg(feature = "virtual-pad")]
+#[repr(C)]
+struct AbsSetup {
+    code: u16,
+    value: i32,
+    minimum: i32,
+    maximum: i32,
+    fuzz: i32,
+    flat: i32,
+    resolution: i32,
+}
+
+#[repr(C)]
+struct InputEvent {
+    tv_sec: i64,
+    tv_usec: i64,
+    kind: u16,
+    code: u16,
+    value: i32,
+}
+
+extern "C" {
+    fn ioctl(fd: c_int, request: c_ulong, ...) -> c_int;
+}
+
+pub struct UinputSink {
+    file: File,
+    // part notches not scrolled yet, x then y
+    scroll: [i32; 2],
+    // events waiting for flush, written with a single syscall
+    pending: Vec<u8>,
+}
+
+impl UinputSink {
+    pub fn new() -> io::Result<UinputSink> {
+        let sink = UinputSink { file: open()?, scroll: [0; 2], pending: Vec::new() };
+
+        sink.ioctl(UI_SET_EVBIT, EV_KEY as c_int)?;
+        sink.ioctl(UI_SET_EVBIT, EV_REL as c_int)?;
+        for key in Key::iter() {
+            if let Some(code) = key_code(key) { sink.ioctl(UI_SET_KEYBIT, code as c_int)?; }
+        }
+        for &button in &[BTN_LEFT, BTN_RIGHT, BTN_MIDDLE] { sink.ioctl(UI_SET_KEYBIT, button as c_int)?; }
+        for &axis in &[REL_X, REL_Y, REL_HWHEEL, REL_WHEEL] { sink.ioctl(UI_SET_RELBIT, axis as c_int)?; }
+
+        create(&sink.file, b"mac-usb-controller")?;
+        Ok(sink)
+    }
+
+    fn ioctl(&self, request: c_ulong, value: c_int) -> io::Result<()> {
+        set(&self.file, request, value)
+    }
+
+    // Each call is one complete event as far as readers of the device are
+    // concerned, so ends in a sync report. Batching only saves the writes,
+    // a key's down and up never land in the same report.
+    fn send(&mut self, events: &[(u16, u16, i32)]) -> Result<(), tfc::Error> {
+        push_events(&mut self.pending, events);
+        Ok(())
+    }
+
+    fn key(&mut self, key: Key, down: bool) -> Result<(), tfc::Error> {
+        match key_code(key) {
+            Some(code) => self.send(&[(EV_KEY, code, down as i32)]),
+            // Fn and NumpadClear have no Linux key, as they don't in tfc
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for UinputSink {
+    fn drop(&mut self) {
+        let _ = self.ioctl(UI_DEV_DESTROY, 0);
+    }
+}
+
+impl OutputSink for UinputSink {
+    fn key_down(&mut self, key: Key) -> Result<(), tfc::Error> {
+        self.key(key, true)
+    }
+
+    fn key_up(&mut self, key: Key) -> Result<(), tfc::Error> {
+        self.key(key, false)
+    }
+
+    fn mouse_down(&mut self, button: MouseButton) -> Result<(), tfc::Error> {
+        self.send(&[(EV_KEY, button_code(button), 1)])
+    }
+
+    fn mouse_up(&mut self, button: MouseButton) -> Result<(), tfc::Error> {
+        self.send(&[(EV_KEY, button_code(button), 0)])
+    }
+
+    fn mouse_move_rel(&mut self, dx: i32, dy: i32) -> Result<(), tfc::Error> {
+        self.send(&[(EV_REL, REL_X, dx), (EV_REL, REL_Y, dy)])
+    }
+
+    fn mouse_scroll(&mut self, dx: i32, dy: i32) -> Result<(), tfc::Error> {
+        self.scroll[0] += dx;
+        self.scroll[1] += dy;
+        let notches = [self.scroll[0] / SCROLL_PER_NOTCH, self.scroll[1] / SCROLL_PER_NOTCH];
+        self.scroll[0] %= SCROLL_PER_NOTCH;
+        self.scroll[1] %= SCROLL_PER_NOTCH;
+
+        if notches == [0, 0] { return Ok(()); }
+        // the wheel counts up away from the user, tfc's y counts down
+        self.send(&[(EV_REL, REL_HWHEEL, notches[0]), (EV_REL, REL_WHEEL, -notches[1])])
+    }
+
+    fn flush(&mut self) -> Result<(), tfc::Error> {
+        if self.pending.is_empty() { return Ok(()); }
+
+        let written = self.file.write_all(&self.pending);
+        self.pending.clear();
+
+        // tfc has nowhere to put an io error, so it's only logged here
+        written.map_err(|e| {
+            debug!("Could not write to /dev/uinput: {}", e);
+            tfc::Error::Unknown
+        })
+    }
+
+    // uinput only knows keys, there's no way to type arbitrary characters
+    fn type_text(&mut self, text: &str) -> Result<(), tfc::Error> {
+        match text.chars().next() {
+            Some(c) => Err(tfc::Error::UnsupportedUnicode(c)),
+            None => Ok(()),
+        }
+    }
+}
+
+// A gamepad the way Linux expects one, with the buttons where they sit on
+// the pad whatever they're labelled, so B is south and A east
+#[cfg(feature = "virtual-pad")]
+pub struct UinputPad {
+    file: File,
+    // last sent, only changes are written
+    last: Option<PadState>,
+}
+
+#[cfg(feature = "virtual-pad")]
+impl UinputPad {
+    pub fn new() -> io::Result<UinputPad> {
+        let file = open()?;
+
+        set(&file, UI_SET_EVBIT, EV_KEY as c_int)?;
+        set(&file, UI_SET_EVBIT, EV_ABS as c_int)?;
+        for &(_, code) in &PAD_BUTTONS { set(&file, UI_SET_KEYBIT, code as c_int)?; }
+        for &(code, minimum, maximum) in &PAD_AXES {
+            set(&file, UI_SET_ABSBIT, code as c_int)?;
+            let setup = AbsSetup { code, value: 0, minimum, maximum, fuzz: 0, flat: 0, resolution: 0 };
+            let result = unsafe { ioctl(file.as_raw_fd(), UI_ABS_SETUP, &setup as *const AbsSetup) };
+            if result == -1 { return Err(io::Error::last_os_error()); }
+        }
+
+        create(&file, b"mac-usb-controller pad")?;
+        Ok(UinputPad { file, last: None })
+    }
+}
+
+#[cfg(feature = "virtual-pad")]
+impl Drop for UinputPad {
+    fn drop(&mut self) {
+        let _ = set(&self.file, UI_DEV_DESTROY, 0);
+    }
+}
+
+#[cfg(feature = "virtual-pad")]
+impl PadSink for UinputPad {
+    fn send(&mut self, state: &PadState) -> Result<(), tfc::Error> {
+        let last = self.last.as_ref();
+        let mut events = Vec::new();
+
+        for &(button, code) in &PAD_BUTTONS {
+            let down = state.buttons.contains(&button);
+            if last.is_none_or(|last| last.buttons.contains(&button) != down) { events.push((EV_KEY, code, down as i32)); }
+        }
+
+        let axes = |state: &PadState| {
+            let [lx, ly, rx, ry] = state.sticks;
+            [lx, ly, rx, ry, state.triggers.0 as i32, state.triggers.1 as i32, state.hat.0, state.hat.1]
+        };
+        let values = axes(state);
+        let old = last.map(axes);
+        for (i, &(code, _, _)) in PAD_AXES.iter().enumerate() {
+            if old.is_none_or(|old| old[i] != values[i]) { events.push((EV_ABS, code, values[i])); }
+        }
+
+        self.last = Some(state.clone());
+        if events.is_empty() { return Ok(()); }
+
+        let mut bytes = Vec::new();
+        push_events(&mut bytes, &events);
+        self.file.write_all(&bytes).map_err(|e| {
+            debug!("Could not write to /dev/uinput: {}", e);
+            tfc::Error::Unknown
+        })
+    }
+}
+
+// from linux/input-event-codes.h, by where they are rather than what
+// they're called
+#[cfg(feature = "virtual-pad")]
+const PAD_BUTTONS: [(Button, u16); 13] = [
+    (Button::B, 0x130),     // BTN_SOUTH
+    (Button::A, 0x131),     // BTN_EAST
+    (Button::X, 0x133),     // BTN_NORTH
+    (Button::Y, 0x134),     // BTN_WEST
+    (Button::L, 0x136),     // BTN_TL
+    (Button::R, 0x137),     // BTN_TR
+    (Button::ZL, 0x138),    // BTN_TL2
+    (Button::ZR, 0x139),    // BTN_TR2
+    (Button::Minus, 0x13a), // BTN_SELECT
+    (Button::Plus, 0x13b),  // BTN_START
+    (Button::Home, 0x13c),  // BTN_MODE
+    (Button::LSB, 0x13d),   // BTN_THUMBL
+    (Button::RSB, 0x13e),   // BTN_THUMBR
+];
+
+fn open() -> io::Result<File> {
+    OpenOptions::new().write(true).custom_flags(O_NONBLOCK).open("/dev/uinput")
+}
+
+fn set(file: &File, request: c_ulong, value: c_int) -> io::Result<()> {
+    let result = unsafe { ioctl(file.as_raw_fd(), request, value) };
+    if result == -1 { return Err(io::Error::last_os_error()); }
+    Ok(())
+}
+
+// Names the device once its keys and axes are set, and makes it
+fn create(file: &File, name: &[u8]) -> io::Result<()> {
+    let mut setup = UinputSetup {
+        id: InputId { bustype: BUS_USB, vendor: 0, product: 0, version: 1 },
+        name: [0; 80],
+        ff_effects_max: 0,
+    };
+    setup.name[..name.len()].copy_from_slice(name);
+
+    let result = unsafe { ioctl(file.as_raw_fd(), UI_DEV_SETUP, &setup as *const UinputSetup) };
+    if result == -1 { return Err(io::Error::last_os_error()); }
+    set(file, UI_DEV_CREATE, 0)
+}
+
+// The events as the kernel reads them, then a sync report ending them
+fn push_events(bytes: &mut Vec<u8>, events: &[(u16, u16, i32)]) {
+    let sync = [(EV_SYN, SYN_REPORT, 0)];
+
+    for &(kind, code, value) in events.iter().chain(sync.iter()) {
+        let event = InputEvent { tv_sec: 0, tv_usec: 0, kind, code, value };
+        let bytes_of = unsafe {
+            std::slice::from_raw_parts(&event as *const InputEvent as *const u8, mem::size_of::<InputEvent>())
+        };
+        bytes.extend_from_slice(bytes_of);
+    }
+}
+
+fn button_code(button: MouseButton) -> u16 {
+    match button {
+        MouseButton::Left => BTN_LEFT,
+        MouseButton::Right => BTN_RIGHT,
+        MouseButton::Middle => BTN_MIDDLE,
+    }
+}
+
+// Linux key codes, the same table tfc uses for its own Linux backend
+fn key_code(key: Key) -> Option<u16> {
+    use Key::*;
+
+    let code = match key {
+        Escape => 1,
+        N1 => 2,
+        N2 => 3,
+        N3 => 4,
+        N4 => 5,
+        N5 => 6,
+        N6 => 7,
+        N7 => 8,
+        N8 => 9,
+        N9 => 10,
+        N0 => 11,
+        Minus => 12,
+        Equal => 13,
+        DeleteOrBackspace => 14,
+        Tab => 15,
+        Q => 16,
+        W => 17,
+        E => 18,
+        R => 19,
+        T => 20,
+        Y => 21,
+        U => 22,
+        I => 23,
+        O => 24,
+        P => 25,
+        LeftBracket => 26,
+        RightBracket => 27,
+        ReturnOrEnter => 28,
+        Control | ControlOrMeta => 29,
+        A => 30,
+        S => 31,
+        D => 32,
+        F => 33,
+        G => 34,
+        H => 35,
+        J => 36,
+        K => 37,
+        L => 38,
+        Semicolon => 39,
+        Quote => 40,
+        Grave => 41,
+        Shift => 42,
+        Backslash => 43,
+        Z => 44,
+        X => 45,
+        C => 46,
+        V => 47,
+        B => 48,
+        N => 49,
+        M => 50,
+        Comma => 51,
+        Period => 52,
+        Slash => 53,
+        RightShift => 54,
+        NumpadMultiply => 55,
+        Alt => 56,
+        Space => 57,
+        CapsLock => 58,
+        F1 => 59,
+        F2 => 60,
+        F3 => 61,
+        F4 => 62,
+        F5 => 63,
+        F6 => 64,
+        F7 => 65,
+        F8 => 66,
+        F9 => 67,
+        F10 => 68,
+        Numpad7 => 71,
+        Numpad8 => 72,
+        Numpad9 => 73,
+        NumpadMinus => 74,
+        Numpad4 => 75,
+        Numpad5 => 76,
+        Numpad6 => 77,
+        NumpadPlus => 78,
+        Numpad1 => 79,
+        Numpad2 => 80,
+        Numpad3 => 81,
+        Numpad0 => 82,
+        NumpadDecimal => 83,
+        F11 => 87,
+        F12 => 88,
+        NumpadEnter => 96,
+        RightControl | RightControlOrMeta => 97,
+        NumpadDivide => 98,
+        RightAlt => 100,
+        Home => 102,
+        UpArrow => 103,
+        PageUp => 104,
+        LeftArrow => 105,
+        RightArrow => 106,
+        End => 107,
+        DownArrow => 108,
+        PageDown => 109,
+        ForwardDelete => 111,
+        Mute => 113,
+        VolumeDown => 114,
+        VolumeUp => 115,
+        NumpadEquals => 117,
+        Meta => 125,
+        RightMeta => 126,
+        PlayPause => 164,
+        Rewind => 168,
+        FastForward => 208,
+        Fn | NumpadClear => return None,
+    };
+
+    Some(code)
+}
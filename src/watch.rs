@@ -0,0 +1,70 @@
+// Reloads the config file whenever it's saved, for --watch
+
+use std::ffi::OsString;
+use std::io;
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use log::{error, info};
+use notify::{DebouncedEvent, RecursiveMode, Watcher};
+
+use crate::config::{self, Config};
+
+// Editors tend to write a file in a few steps, wait for them to settle
+const SETTLE_TIME: Duration = Duration::from_millis(200);
+
+// Loads and checks the file on its own thread and only passes on configs
+// that are valid, so a bad save leaves the running one alone
+pub struct ConfigWatcher {
+    rx: mpsc::Receiver<Config>,
+}
+
+impl ConfigWatcher {
+    pub fn start(path: &Path) -> io::Result<ConfigWatcher> {
+        let (events_tx, events) = mpsc::channel();
+        let mut watcher = notify::watcher(events_tx, SETTLE_TIME).map_err(io::Error::other)?;
+
+        // Saving often replaces the file rather than writing to it, which
+        // would end a watch on the file itself, so watch its directory
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        watcher.watch(dir, RecursiveMode::NonRecursive).map_err(io::Error::other)?;
+
+        let path = path.to_path_buf();
+        let name = path.file_name().map(OsString::from);
+        let (tx, rx) = mpsc::channel();
+
+        thread::Builder::new().name("config-watcher".to_string()).spawn(move || {
+            // stops watching when the thread ends
+            let _watcher = watcher;
+
+            for event in events {
+                let changed = match &event {
+                    DebouncedEvent::Create(changed) | DebouncedEvent::Write(changed) | DebouncedEvent::Rename(_, changed) => changed,
+                    _ => continue,
+                };
+                if changed.file_name().map(OsString::from) != name { continue; }
+
+                match config::load(&path) {
+                    Ok(config) => {
+                        info!("Reloaded {:?}", path);
+                        // dispatch has finished with us
+                        if tx.send(config).is_err() { return; }
+                    },
+                    Err(e) => error!("Could not reload {:?}, keeping the old config: {}", path, e),
+                }
+            }
+        })?;
+
+        Ok(ConfigWatcher { rx })
+    }
+
+    // The newest valid config since the last call, if there is one
+    pub fn changed(&self) -> Option<Config> {
+        self.rx.try_iter().last()
+    }
+}
@@ -0,0 +1,101 @@
+// Each tests/fixtures/NAME.txt is a recording, as --record writes them and
+// --replay reads them back, and NAME.events is every event the default
+// config should send for it, one per line the way --dry-run logs them:
+//
+//   button_mash    face buttons, shoulders and Minus+Plus held in overlapping runs
+//   dpad_roll      the dpad all the way round clockwise from up, then let go
+//   diagonals      from a diagonal straight to the opposite one and the one beside it
+//   rapid_presses  A tapped every report, with B held down across some of the taps
+//
+// Timestamps are ignored and there's no debounce, so every report counts.
+// UPDATE_FIXTURES=1 writes what was sent as the new .events instead.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use mac_usb_controller::config::Config;
+use mac_usb_controller::record::Replay;
+use mac_usb_controller::sink::MemorySink;
+use mac_usb_controller::Controller;
+
+fn fixtures() -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let mut found: Vec<PathBuf> = fs::read_dir(&dir).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "txt"))
+        .collect();
+    found.sort();
+    found
+}
+
+fn replay(recording: &Path) -> Vec<String> {
+    let config = Config { debounce: None, ..Config::default() };
+    let mut controller = Controller::new(&config, 0);
+    let mut sink = MemorySink::default();
+
+    for report in Replay::open(recording).unwrap() {
+        let (_, data) = report.unwrap();
+        controller.process(&data, &mut sink).unwrap();
+    }
+
+    sink.sent.iter().map(|event| event.to_string()).collect()
+}
+
+// Expected and actual a line each, "-" for one only expected, "+" for one
+// only sent, lined up on the longest run they have in common
+fn diff(expected: &[String], actual: &[String]) -> String {
+    let (n, m) = (expected.len(), actual.len());
+    let mut common = vec![vec![0; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            common[i][j] = if expected[i] == actual[j] { common[i + 1][j + 1] + 1 } else { common[i + 1][j].max(common[i][j + 1]) };
+        }
+    }
+
+    let mut out = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && expected[i] == actual[j] {
+            out += &format!("  {}\n", expected[i]);
+            i += 1;
+            j += 1;
+        } else if j < m && (i == n || common[i][j + 1] >= common[i + 1][j]) {
+            out += &format!("+ {}\n", actual[j]);
+            j += 1;
+        } else {
+            out += &format!("- {}\n", expected[i]);
+            i += 1;
+        }
+    }
+    out
+}
+
+#[test]
+fn fixtures_send_their_events() {
+    let update = env::var_os("UPDATE_FIXTURES").is_some();
+    let mut failed = Vec::new();
+
+    for recording in fixtures() {
+        let events = recording.with_extension("events");
+        let actual = replay(&recording);
+
+        if update {
+            fs::write(&events, actual.iter().map(|line| format!("{}\n", line)).collect::<String>()).unwrap();
+            continue;
+        }
+
+        let expected: Vec<String> = fs::read_to_string(&events)
+            .unwrap_or_else(|e| panic!("Could not read {:?}: {}", events, e))
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(str::to_string)
+            .collect();
+
+        if expected != actual {
+            failed.push(format!("{} (- expected, + sent):\n{}", recording.display(), diff(&expected, &actual)));
+        }
+    }
+
+    assert!(failed.is_empty(), "\n{}", failed.join("\n"));
+}
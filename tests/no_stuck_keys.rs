@@ -0,0 +1,103 @@
+// Random reports through a Controller, ending with the pad let go, must
+// leave nothing held and never press a key or mouse button that's already
+// down.
+
+use mac_usb_controller::config::{Config, DpadMode, Socd, StickMode};
+use mac_usb_controller::sink::{MemorySink, Sent};
+use mac_usb_controller::{Controller, Input, REPORT_LEN};
+use quickcheck::{Arbitrary, Gen, QuickCheck, TestResult};
+
+const NEUTRAL: [u8; REPORT_LEN] = [0, 0, 0x0f, 0x80, 0x80, 0x80, 0x80, 0];
+
+// Positions the dpad sends, 0-7 clockwise from up and anything else off
+const DPAD: [u8; 11] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 0x0f, 0xff];
+// Centered, either way past the deadzone and the WASD threshold, and the ends
+const STICK: [u8; 7] = [0x00, 0x20, 0x60, 0x80, 0xa0, 0xe0, 0xff];
+
+#[derive(Debug, Clone)]
+struct Case {
+    socd: Socd,
+    dpad_mode: DpadMode,
+    stick_modes: [StickMode; 2],
+    reports: Vec<[u8; REPORT_LEN]>,
+}
+
+impl Arbitrary for Case {
+    fn arbitrary(g: &mut Gen) -> Case {
+        let stick_modes = [StickMode::Mouse, StickMode::Wasd, StickMode::Scroll, StickMode::Off];
+        let len = usize::arbitrary(g) % (g.size() + 1);
+        let reports = (0..len).map(|_| [
+            u8::arbitrary(g),
+            u8::arbitrary(g),
+            *g.choose(&DPAD).unwrap(),
+            *g.choose(&STICK).unwrap(),
+            *g.choose(&STICK).unwrap(),
+            *g.choose(&STICK).unwrap(),
+            *g.choose(&STICK).unwrap(),
+            0,
+        ]).collect();
+
+        Case {
+            socd: *g.choose(&[Socd::Neutral, Socd::LastInput, Socd::UpPriority]).unwrap(),
+            dpad_mode: *g.choose(&[DpadMode::Cardinals, DpadMode::Octants]).unwrap(),
+            stick_modes: [*g.choose(&stick_modes).unwrap(), *g.choose(&stick_modes).unwrap()],
+            reports,
+        }
+    }
+}
+
+fn config(case: &Case) -> Config {
+    let mut config = Config {
+        // every report counts, rather than only the ones a debounce apart
+        debounce: None,
+        socd: case.socd,
+        dpad_mode: case.dpad_mode,
+        ..Config::default()
+    };
+    config.profiles[0].lstick_mode = case.stick_modes[0];
+    config.profiles[0].rstick_mode = case.stick_modes[1];
+    config
+}
+
+// Err with the first event that breaks the rule, or whatever's still held
+fn check(sent: &[Sent]) -> Result<(), String> {
+    let mut keys = Vec::new();
+    let mut buttons = Vec::new();
+
+    for (i, event) in sent.iter().enumerate() {
+        let pressed = match event {
+            Sent::KeyDown(key) if keys.contains(key) => false,
+            Sent::KeyDown(key) => { keys.push(*key); true },
+            Sent::KeyUp(key) => { keys.retain(|held| held != key); true },
+            Sent::MouseDown(button) if buttons.contains(button) => false,
+            Sent::MouseDown(button) => { buttons.push(*button); true },
+            Sent::MouseUp(button) => { buttons.retain(|held| held != button); true },
+            _ => true,
+        };
+        if !pressed { return Err(format!("event {}, {}, is already down", i, event)); }
+    }
+
+    if keys.is_empty() && buttons.is_empty() { Ok(()) }
+    else { Err(format!("still held at the end: keys {:?}, mouse buttons {:?}", keys, buttons)) }
+}
+
+fn nothing_left_held(case: Case) -> TestResult {
+    let mut controller = Controller::new(&config(&case), 0);
+    let mut sink = MemorySink::default();
+
+    for &report in case.reports.iter().chain(std::iter::once(&NEUTRAL)) {
+        if let Err(e) = controller.update(Input::new(report), &mut sink) {
+            return TestResult::error(format!("update failed: {:?}", e));
+        }
+    }
+
+    match check(&sink.sent) {
+        Ok(()) => TestResult::passed(),
+        Err(message) => TestResult::error(message),
+    }
+}
+
+#[test]
+fn no_stuck_keys() {
+    QuickCheck::new().tests(500).quickcheck(nothing_left_held as fn(Case) -> TestResult);
+}